@@ -1,9 +1,149 @@
 #![no_std]
 
+pub mod link;
+pub mod orientation;
+pub mod serial;
+
 use defmt::Format;
+use heapless::String;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Format, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Format, Clone)]
 pub enum Message {
     Button(bool),
+    /// Total number of messages the firmware had to drop because the host did not keep up.
+    Dropped(u32),
+    /// Sent periodically by both sides to show they are still there, see [`link`].
+    Heartbeat,
+    /// First message after the firmware booted.
+    Boot(ResetReason),
+    /// The firmware panicked before the last reset, sent right after [`Message::Boot`].
+    CrashReport(CrashReport),
+    /// Reading of a sensor on another board, forwarded by the uart-2-boards display board.
+    SensorReading(SensorReading),
+    /// Latest orientation from the IMU's DMP, see [`orientation`].
+    Orientation(orientation::Orientation),
+    /// Sent by the host: the IMU should calibrate again instead of using its stored offsets.
+    Recalibrate,
+    /// Total number of times the IMU's FIFO overflowed, orientation samples were lost each time.
+    FifoOverflow(u32),
+}
+
+/// Why the firmware (re)started.
+#[derive(Debug, Serialize, Deserialize, Format, Clone, Copy, PartialEq, Eq)]
+pub enum ResetReason {
+    PowerOn,
+    Software,
+    /// A task wedged and the watchdog pulled the plug.
+    Watchdog,
+    BrownOut,
+    /// Reset via USB, e.g. by flashing or opening the serial monitor.
+    Usb,
+    /// Chip specific reset reason code.
+    Other(u8),
+    Unknown,
+}
+
+/// One validated reading as it arrived over the board-to-board UART link.
+///
+/// The sensor ID refers to the registry of the uart-2-boards `protocol` crate.
+#[derive(Debug, Serialize, Deserialize, Format, Clone, Copy, PartialEq)]
+pub struct SensorReading {
+    pub sensor_id: u8,
+    pub value: f32,
+    /// Reading counter of the sending board, gaps mean lost readings.
+    pub timestamp: u32,
+}
+
+/// Where and why the firmware panicked.
+#[derive(Debug, Serialize, Deserialize, Format, Clone, PartialEq, Eq)]
+pub struct CrashReport {
+    pub message: String<{ CrashReport::MESSAGE_LEN }>,
+    pub file: String<{ CrashReport::FILE_LEN }>,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl CrashReport {
+    pub const MESSAGE_LEN: usize = 96;
+    pub const FILE_LEN: usize = 64;
+
+    /// Longer message and file name are cut off at the maximum length.
+    pub fn new(message: &str, file: &str, line: u32, column: u32) -> Self {
+        Self {
+            message: truncated(message),
+            file: truncated(file),
+            line,
+            column,
+        }
+    }
+}
+
+fn truncated<const N: usize>(text: &str) -> String<N> {
+    let mut end = text.len().min(N);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut truncated = String::new();
+    _ = truncated.push_str(&text[..end]);
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `len` ASCII letters followed by `tail`
+    fn text(len: usize, tail: &str) -> String<256> {
+        let mut text = String::new();
+        for _ in 0..len {
+            text.push('x').unwrap();
+        }
+        text.push_str(tail).unwrap();
+        text
+    }
+
+    #[test]
+    fn long_messages_are_cut_off() {
+        let message = text(CrashReport::MESSAGE_LEN + 10, "");
+        let report = CrashReport::new(&message, "main.rs", 1, 2);
+        assert_eq!(report.message, &message[..CrashReport::MESSAGE_LEN]);
+        assert_eq!(report.file, "main.rs");
+
+        let short = CrashReport::new("oops", "main.rs", 1, 2);
+        assert_eq!(short.message, "oops");
+    }
+
+    #[test]
+    fn cuts_end_before_a_character_straddling_them() {
+        // 'ü' takes two bytes, the second one would be the first beyond the limit
+        let message = text(CrashReport::MESSAGE_LEN - 1, "üz");
+        let report = CrashReport::new(&message, "main.rs", 1, 2);
+        assert_eq!(report.message.len(), CrashReport::MESSAGE_LEN - 1);
+        assert_eq!(report.message, &message[..CrashReport::MESSAGE_LEN - 1]);
+
+        // Three bytes, cut after its first one
+        let message = text(CrashReport::MESSAGE_LEN - 1, "€");
+        let report = CrashReport::new(&message, "main.rs", 1, 2);
+        assert_eq!(report.message.len(), CrashReport::MESSAGE_LEN - 1);
+    }
+
+    #[test]
+    fn long_file_names_are_cut_off() {
+        let file = text(CrashReport::FILE_LEN + 1, ".rs");
+        let report = CrashReport::new("oops", &file, 1, 2);
+        assert_eq!(report.file, &file[..CrashReport::FILE_LEN]);
+    }
+
+    #[test]
+    fn crash_reports_survive_the_link() {
+        let report = CrashReport::new("index out of bounds: ü", "src/main.rs", 42, 7);
+        let message = Message::CrashReport(report.clone());
+        let mut buffer = [0u8; 256];
+        let frame = postcard::to_slice_cobs(&message, &mut buffer).unwrap();
+        match postcard::from_bytes_cobs::<Message>(frame).unwrap() {
+            Message::CrashReport(received) => assert_eq!(received, report),
+            other => panic!("decoded as {other:?}"),
+        }
+    }
 }
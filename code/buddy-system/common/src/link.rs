@@ -0,0 +1,132 @@
+//! Liveness tracking of the other side of the link, used by firmware and host alike.
+
+/// How often each side sends a [`crate::Message::Heartbeat`] when it has nothing else to say.
+pub const HEARTBEAT_INTERVAL_MS: u64 = 500;
+
+/// After this much silence the peer is considered gone.
+pub const LINK_TIMEOUT_MS: u64 = 2_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkEvent {
+    /// We hear from the peer (again).
+    Alive,
+    /// The peer was silent for longer than the timeout.
+    Lost,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LinkState {
+    Unknown,
+    Alive,
+    Lost,
+}
+
+/// Decides whether the peer is alive based on when we last received anything from it.
+///
+/// Timestamps are milliseconds from any monotonic clock.
+pub struct LinkMonitor {
+    timeout_ms: u64,
+    last_seen: Option<u64>,
+    state: LinkState,
+}
+
+impl LinkMonitor {
+    pub const fn new(timeout_ms: u64) -> Self {
+        Self {
+            timeout_ms,
+            last_seen: None,
+            state: LinkState::Unknown,
+        }
+    }
+
+    /// Call for every message received from the peer.
+    pub fn on_message(&mut self, now_ms: u64) -> Option<LinkEvent> {
+        self.last_seen = Some(now_ms);
+        if self.state == LinkState::Alive {
+            return None;
+        }
+        self.state = LinkState::Alive;
+        Some(LinkEvent::Alive)
+    }
+
+    /// Call regularly, or at [`LinkMonitor::next_deadline`], to detect silence.
+    ///
+    /// A peer we never heard from is reported as lost one timeout after the first poll.
+    pub fn poll(&mut self, now_ms: u64) -> Option<LinkEvent> {
+        if self.state == LinkState::Lost {
+            return None;
+        }
+        let last_seen = *self.last_seen.get_or_insert(now_ms);
+        if now_ms.saturating_sub(last_seen) < self.timeout_ms {
+            return None;
+        }
+        self.state = LinkState::Lost;
+        Some(LinkEvent::Lost)
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.state == LinkState::Alive
+    }
+
+    /// When the peer will be considered lost if nothing arrives until then.
+    pub fn next_deadline(&self) -> Option<u64> {
+        match self.state {
+            LinkState::Lost => None,
+            _ => self.last_seen.map(|last_seen| last_seen + self.timeout_ms),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_peer_never_heard_from_is_lost_one_timeout_after_the_first_poll() {
+        let mut monitor = LinkMonitor::new(100);
+        assert_eq!(monitor.next_deadline(), None);
+        assert_eq!(monitor.poll(1_000), None);
+        assert_eq!(monitor.next_deadline(), Some(1_100));
+        assert_eq!(monitor.poll(1_099), None);
+        assert_eq!(monitor.poll(1_100), Some(LinkEvent::Lost));
+        assert!(!monitor.is_alive());
+    }
+
+    #[test]
+    fn each_change_is_reported_once() {
+        let mut monitor = LinkMonitor::new(100);
+        assert_eq!(monitor.on_message(0), Some(LinkEvent::Alive));
+        assert_eq!(monitor.on_message(50), None);
+        assert!(monitor.is_alive());
+        assert_eq!(monitor.poll(149), None);
+
+        assert_eq!(monitor.poll(150), Some(LinkEvent::Lost));
+        assert_eq!(monitor.poll(151), None);
+        assert_eq!(monitor.poll(10_000), None);
+
+        assert_eq!(monitor.on_message(10_001), Some(LinkEvent::Alive));
+        assert_eq!(monitor.on_message(10_002), None);
+        assert_eq!(monitor.poll(10_003), None);
+    }
+
+    #[test]
+    fn no_deadline_while_lost() {
+        let mut monitor = LinkMonitor::new(100);
+        monitor.on_message(0);
+        assert_eq!(monitor.next_deadline(), Some(100));
+        monitor.poll(100);
+        assert_eq!(monitor.next_deadline(), None);
+
+        monitor.on_message(500);
+        assert_eq!(monitor.next_deadline(), Some(600));
+    }
+
+    #[test]
+    fn a_clock_going_backwards_counts_as_no_time_passed() {
+        let mut monitor = LinkMonitor::new(100);
+        monitor.on_message(1_000);
+        assert_eq!(monitor.poll(10), None);
+        assert!(monitor.is_alive());
+        assert_eq!(monitor.poll(1_100), Some(LinkEvent::Lost));
+    }
+}
@@ -0,0 +1,296 @@
+//! Orientation from the quaternions of the MPU6050's DMP, kept apart from the firmware so it
+//! can be tested on the host.
+
+use defmt::Format;
+use serde::{Deserialize, Serialize};
+
+/// Size of one DMP FIFO packet: the quaternion, then raw accelerometer and gyroscope values.
+pub const DMP_PACKET_SIZE: usize = 28;
+
+/// How often the DMP writes a packet into the FIFO: 1 kHz / (1 + 4), with the sample rate
+/// divider `mpu6050-dmp` sets up for it. The fastest rate orientations can be sampled at.
+pub const DMP_RATE_HZ: u32 = 200;
+
+/// The DMP's fixed point format for quaternion components, 1.0 is `1 << 30`.
+const DMP_QUATERNION_SCALE: f32 = (1u32 << 30) as f32;
+
+/// Unit quaternion describing how the sensor is rotated.
+#[derive(Debug, Format, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+/// A direction or acceleration in the frame of the sensor.
+#[derive(Debug, Format, Clone, Copy, PartialEq)]
+pub struct Vector3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+/// Rotation about the x (roll), y (pitch) and z (yaw) axis in degrees, applied in the order
+/// yaw, pitch, roll.
+#[derive(Debug, Serialize, Deserialize, Format, Clone, Copy, PartialEq)]
+pub struct Orientation {
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+}
+
+impl Quaternion {
+    pub const IDENTITY: Self = Self {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    /// The quaternion at the start of a DMP packet: w, x, y and z as big endian `i32`.
+    ///
+    /// `None` for a packet of zeros, which the DMP sends before it has a first estimate.
+    pub fn from_dmp_packet(packet: &[u8; DMP_PACKET_SIZE]) -> Option<Self> {
+        let component = |index: usize| {
+            let bytes = [
+                packet[4 * index],
+                packet[4 * index + 1],
+                packet[4 * index + 2],
+                packet[4 * index + 3],
+            ];
+            i32::from_be_bytes(bytes) as f32 / DMP_QUATERNION_SCALE
+        };
+        Self {
+            w: component(0),
+            x: component(1),
+            y: component(2),
+            z: component(3),
+        }
+        .normalized()
+    }
+
+    /// Scaled to unit length, which the DMP's fixed point values only roughly have.
+    pub fn normalized(self) -> Option<Self> {
+        let norm =
+            libm::sqrtf(self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z);
+        if norm < f32::EPSILON {
+            return None;
+        }
+        Some(Self {
+            w: self.w / norm,
+            x: self.x / norm,
+            y: self.y / norm,
+            z: self.z / norm,
+        })
+    }
+
+    /// Where gravity pulls in the frame of the sensor, unit length. `(0, 0, 1)` when the sensor
+    /// lies flat, which is the direction the accelerometer measures 1 g in.
+    pub fn gravity(&self) -> Vector3 {
+        let Self { w, x, y, z } = *self;
+        Vector3 {
+            x: 2.0 * (x * z - w * y),
+            y: 2.0 * (w * x + y * z),
+            z: w * w - x * x - y * y + z * z,
+        }
+    }
+
+    pub fn to_euler(&self) -> Orientation {
+        let Self { w, x, y, z } = *self;
+        let roll = libm::atan2f(2.0 * (w * x + y * z), 1.0 - 2.0 * (x * x + y * y));
+        // Rounding can push it slightly beyond ±1 pointing straight up or down
+        let pitch = libm::asinf((2.0 * (w * y - z * x)).clamp(-1.0, 1.0));
+        let yaw = libm::atan2f(2.0 * (w * z + x * y), 1.0 - 2.0 * (y * y + z * z));
+        Orientation {
+            roll: roll.to_degrees(),
+            pitch: pitch.to_degrees(),
+            yaw: yaw.to_degrees(),
+        }
+    }
+}
+
+/// Picks the packets for a sample rate below [`DMP_RATE_HZ`], spread as evenly as the packets
+/// allow. Every packet is counted once, so no sample is taken twice and none is skipped as long
+/// as no packet is lost.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct Decimator {
+    rate_hz: u32,
+    credit: u32,
+}
+
+impl Decimator {
+    /// `None` for a rate of 0 or faster than the DMP.
+    pub const fn new(rate_hz: u32) -> Option<Self> {
+        if rate_hz == 0 || rate_hz > DMP_RATE_HZ {
+            return None;
+        }
+        Some(Self {
+            rate_hz,
+            // The first packet is a sample, the consumers don't wait for a full period
+            credit: DMP_RATE_HZ - rate_hz,
+        })
+    }
+
+    pub fn rate_hz(&self) -> u32 {
+        self.rate_hz
+    }
+
+    /// To be called for every packet read from the FIFO, in order. `true` if it is a sample.
+    pub fn on_packet(&mut self) -> bool {
+        self.credit += self.rate_hz;
+        if self.credit >= DMP_RATE_HZ {
+            self.credit -= DMP_RATE_HZ;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rotation by `degrees` about the unit `axis`
+    fn rotation(axis: [f32; 3], degrees: f32) -> Quaternion {
+        let half = degrees.to_radians() / 2.0;
+        let (sin, cos) = (libm::sinf(half), libm::cosf(half));
+        Quaternion {
+            w: cos,
+            x: axis[0] * sin,
+            y: axis[1] * sin,
+            z: axis[2] * sin,
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-3, "{actual} != {expected}");
+    }
+
+    fn assert_orientation(orientation: Orientation, roll: f32, pitch: f32, yaw: f32) {
+        assert_close(orientation.roll, roll);
+        assert_close(orientation.pitch, pitch);
+        assert_close(orientation.yaw, yaw);
+    }
+
+    fn packet(components: [i32; 4]) -> [u8; DMP_PACKET_SIZE] {
+        let mut packet = [0xAA; DMP_PACKET_SIZE];
+        for (chunk, component) in packet.chunks_mut(4).zip(components) {
+            chunk.copy_from_slice(&component.to_be_bytes());
+        }
+        packet
+    }
+
+    #[test]
+    fn dmp_packets_are_read_and_normalized() {
+        assert_eq!(
+            Quaternion::from_dmp_packet(&packet([1 << 30, 0, 0, 0])),
+            Some(Quaternion::IDENTITY)
+        );
+
+        // Half of 90° about z, a bit off unit length like the DMP sends it
+        let half = (0.7 * (1 << 30) as f32) as i32;
+        let quaternion = Quaternion::from_dmp_packet(&packet([half, 0, 0, half])).unwrap();
+        assert_close(quaternion.w, core::f32::consts::FRAC_1_SQRT_2);
+        assert_close(quaternion.z, core::f32::consts::FRAC_1_SQRT_2);
+        assert_orientation(quaternion.to_euler(), 0.0, 0.0, 90.0);
+
+        assert_eq!(Quaternion::from_dmp_packet(&packet([0; 4])), None);
+    }
+
+    #[test]
+    fn rotations_about_one_axis_become_that_angle() {
+        assert_orientation(Quaternion::IDENTITY.to_euler(), 0.0, 0.0, 0.0);
+        assert_orientation(rotation([1.0, 0.0, 0.0], 30.0).to_euler(), 30.0, 0.0, 0.0);
+        assert_orientation(rotation([0.0, 1.0, 0.0], -45.0).to_euler(), 0.0, -45.0, 0.0);
+        assert_orientation(rotation([0.0, 0.0, 1.0], 170.0).to_euler(), 0.0, 0.0, 170.0);
+        assert_orientation(
+            rotation([0.0, 0.0, 1.0], -170.0).to_euler(),
+            0.0,
+            0.0,
+            -170.0,
+        );
+    }
+
+    #[test]
+    fn euler_angles_combine_yaw_pitch_roll() {
+        let yaw = rotation([0.0, 0.0, 1.0], 60.0);
+        let pitch = rotation([0.0, 1.0, 0.0], 20.0);
+        let roll = rotation([1.0, 0.0, 0.0], -10.0);
+        let product = |a: Quaternion, b: Quaternion| Quaternion {
+            w: a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+            x: a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+            y: a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+            z: a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+        };
+        let combined = product(product(yaw, pitch), roll);
+        assert_orientation(combined.to_euler(), -10.0, 20.0, 60.0);
+    }
+
+    #[test]
+    fn pitch_stays_defined_pointing_straight_up() {
+        // Slightly more than 90° as rounding could make it
+        let up = Quaternion {
+            w: 0.7072,
+            x: 0.0,
+            y: 0.7072,
+            z: 0.0,
+        };
+        assert_close(up.to_euler().pitch, 90.0);
+    }
+
+    #[test]
+    fn gravity_follows_the_tilt() {
+        let flat = Quaternion::IDENTITY.gravity();
+        assert_eq!(
+            flat,
+            Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0
+            }
+        );
+
+        let on_its_side = rotation([1.0, 0.0, 0.0], 90.0).gravity();
+        assert_close(on_its_side.x, 0.0);
+        assert_close(on_its_side.y, 1.0);
+        assert_close(on_its_side.z, 0.0);
+
+        // Yaw doesn't change where down is
+        let turned = rotation([0.0, 0.0, 1.0], 123.0).gravity();
+        assert_close(turned.z, 1.0);
+    }
+
+    /// Indices of the sampled packets among the first `packets`
+    fn samples(rate_hz: u32, packets: usize) -> heapless::Vec<usize, 2000> {
+        let mut decimator = Decimator::new(rate_hz).unwrap();
+        (0..packets).filter(|_| decimator.on_packet()).collect()
+    }
+
+    #[test]
+    fn decimation_keeps_the_rate_exactly() {
+        for rate in [1, 7, 30, 50, 199, 200] {
+            let samples = samples(rate, 10 * DMP_RATE_HZ as usize);
+            assert_eq!(samples.len(), 10 * rate as usize, "at {rate} Hz");
+        }
+        assert_eq!(samples(1, 2 * DMP_RATE_HZ as usize), [0, 200]);
+        assert_eq!(samples(50, 12), [0, 4, 8]);
+    }
+
+    #[test]
+    fn decimation_spreads_the_samples_evenly() {
+        // 200 / 30 is 6.67 packets, so the gaps are 6 or 7 and never further off
+        let samples = samples(30, 1000);
+        for gap in samples.windows(2).map(|pair| pair[1] - pair[0]) {
+            assert!(gap == 6 || gap == 7, "gap of {gap}");
+        }
+    }
+
+    #[test]
+    fn decimation_rejects_rates_the_dmp_cannot_deliver() {
+        assert_eq!(Decimator::new(0), None);
+        assert_eq!(Decimator::new(DMP_RATE_HZ + 1), None);
+        assert_eq!(Decimator::new(DMP_RATE_HZ).unwrap().rate_hz(), DMP_RATE_HZ);
+    }
+}
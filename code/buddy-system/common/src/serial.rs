@@ -0,0 +1,228 @@
+//! The firmware side of the serial link to the host: the queue of outgoing messages and the
+//! framing of incoming ones. Every firmware talking to the host uses these.
+//!
+//! Messages go over the wire as postcard in COBS frames, each ending with a zero byte.
+
+use core::cell::Cell;
+
+use defmt::Format;
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    channel::{Channel, TrySendError},
+};
+use postcard::accumulator::{CobsAccumulator, FeedResult};
+
+use crate::Message;
+
+#[derive(Clone, Copy)]
+struct Drops {
+    total: u32,
+    reported: u32,
+}
+
+/// Messages waiting for the host, `N` at most.
+///
+/// Nobody ever waits on the host. If it stops reading, the queue fills up and the oldest
+/// messages are dropped in favour of the newest ones, the freshest state is what matters. The
+/// number of dropped messages is reported once the link moves again.
+pub struct Outbox<const N: usize> {
+    channel: Channel<CriticalSectionRawMutex, Message, N>,
+    drops: Mutex<CriticalSectionRawMutex, Cell<Drops>>,
+}
+
+impl<const N: usize> Outbox<N> {
+    pub const fn new() -> Self {
+        Self {
+            channel: Channel::new(),
+            drops: Mutex::new(Cell::new(Drops {
+                total: 0,
+                reported: 0,
+            })),
+        }
+    }
+
+    /// Queue a message, dropping the oldest queued one if the queue is full.
+    pub fn send(&self, message: Message) {
+        let mut message = message;
+        while let Err(TrySendError::Full(rejected)) = self.channel.try_send(message) {
+            message = rejected;
+            if self.channel.try_receive().is_ok() {
+                self.drops.lock(|drops| {
+                    let mut counts = drops.get();
+                    counts.total = counts.total.wrapping_add(1);
+                    drops.set(counts);
+                });
+            }
+        }
+    }
+
+    /// The oldest queued message, once there is one.
+    pub async fn receive(&self) -> Message {
+        self.channel.receive().await
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channel.is_empty()
+    }
+
+    /// Total number of messages dropped since boot.
+    pub fn dropped(&self) -> u32 {
+        self.drops.lock(|drops| drops.get().total)
+    }
+
+    /// The [`Message::Dropped`] for the host if messages were dropped since the last report,
+    /// along with how many.
+    pub fn take_drop_report(&self) -> Option<(u32, Message)> {
+        self.drops.lock(|drops| {
+            let mut counts = drops.get();
+            if counts.total == counts.reported {
+                return None;
+            }
+            let new = counts.total.wrapping_sub(counts.reported);
+            counts.reported = counts.total;
+            drops.set(counts);
+            Some((new, Message::Dropped(counts.total)))
+        })
+    }
+}
+
+impl<const N: usize> Default for Outbox<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A complete frame that isn't a message we know, e.g. from a newer host.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidFrame;
+
+/// Picks the messages out of the bytes received, in frames of up to `N` bytes.
+pub struct MessageReader<const N: usize> {
+    accumulator: CobsAccumulator<N>,
+}
+
+impl<const N: usize> MessageReader<N> {
+    pub const fn new() -> Self {
+        Self {
+            accumulator: CobsAccumulator::new(),
+        }
+    }
+
+    /// Hand every frame completed by `bytes` to `on_frame`. The rest is kept for the next call.
+    ///
+    /// A frame too long to be one of ours is skipped without a word, reading starts over after
+    /// its zero byte.
+    pub fn feed(&mut self, bytes: &[u8], mut on_frame: impl FnMut(Result<Message, InvalidFrame>)) {
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            bytes = match self.accumulator.feed::<Message>(bytes) {
+                FeedResult::Consumed => break,
+                FeedResult::OverFull(remaining) => remaining,
+                FeedResult::DeserError(remaining) => {
+                    on_frame(Err(InvalidFrame));
+                    remaining
+                }
+                FeedResult::Success { data, remaining } => {
+                    on_frame(Ok(data));
+                    remaining
+                }
+            };
+        }
+    }
+}
+
+impl<const N: usize> Default for MessageReader<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(message: &Message) -> heapless::Vec<u8, 64> {
+        let mut buffer = [0u8; 64];
+        let frame = postcard::to_slice_cobs(message, &mut buffer).unwrap();
+        heapless::Vec::from_slice(frame).unwrap()
+    }
+
+    fn read<const N: usize>(
+        reader: &mut MessageReader<N>,
+        bytes: &[u8],
+    ) -> heapless::Vec<Result<Message, InvalidFrame>, 8> {
+        let mut frames = heapless::Vec::new();
+        reader.feed(bytes, |frame| frames.push(frame).unwrap());
+        frames
+    }
+
+    #[test]
+    fn a_full_outbox_drops_the_oldest_and_reports_it_once() {
+        let outbox = Outbox::<2>::new();
+        outbox.send(Message::Button(false));
+        assert!(outbox.take_drop_report().is_none());
+
+        outbox.send(Message::Heartbeat);
+        outbox.send(Message::Recalibrate);
+        outbox.send(Message::Button(true));
+        assert_eq!(outbox.dropped(), 2);
+        assert!(matches!(
+            outbox.channel.try_receive(),
+            Ok(Message::Recalibrate)
+        ));
+        assert!(matches!(
+            outbox.channel.try_receive(),
+            Ok(Message::Button(true))
+        ));
+        assert!(outbox.is_empty());
+
+        assert!(matches!(
+            outbox.take_drop_report(),
+            Some((2, Message::Dropped(2)))
+        ));
+        assert!(outbox.take_drop_report().is_none());
+
+        // The host learns the total, the log how many are new
+        for _ in 0..3 {
+            outbox.send(Message::Heartbeat);
+        }
+        assert!(matches!(
+            outbox.take_drop_report(),
+            Some((1, Message::Dropped(3)))
+        ));
+    }
+
+    #[test]
+    fn frames_split_across_reads_are_put_back_together() {
+        let mut reader = MessageReader::<32>::new();
+        let first = frame(&Message::Recalibrate);
+        let second = frame(&Message::Dropped(7));
+        let mut bytes: heapless::Vec<u8, 64> = heapless::Vec::new();
+        bytes.extend_from_slice(&first).unwrap();
+        bytes.extend_from_slice(&second).unwrap();
+
+        let (start, rest) = bytes.split_at(first.len() + 1);
+        let frames = read(&mut reader, start);
+        assert!(matches!(frames.as_slice(), [Ok(Message::Recalibrate)]));
+        let frames = read(&mut reader, rest);
+        assert!(matches!(frames.as_slice(), [Ok(Message::Dropped(7))]));
+    }
+
+    #[test]
+    fn garbage_and_long_frames_are_skipped() {
+        let mut reader = MessageReader::<8>::new();
+        let message = frame(&Message::Heartbeat);
+
+        // Not a message at all
+        let frames = read(&mut reader, &[0xff, 0xff, 0x00]);
+        assert!(matches!(frames.as_slice(), [Err(InvalidFrame)]));
+
+        // Longer than the reader takes, the next frame still gets through
+        let mut bytes: heapless::Vec<u8, 64> = heapless::Vec::new();
+        bytes.extend_from_slice(&[0x01; 20]).unwrap();
+        bytes.push(0x00).unwrap();
+        bytes.extend_from_slice(&message).unwrap();
+        let frames = read(&mut reader, &bytes);
+        assert!(matches!(frames.as_slice(), [Ok(Message::Heartbeat)]));
+    }
+}
@@ -0,0 +1,138 @@
+//! Panic handler that survives the reset.
+//!
+//! The panic message and location are written to RTC fast RAM, which is not cleared by a
+//! software reset. After the reboot [`take`] hands them out once as a [`CrashReport`] for the
+//! host, so crashes are not lost when no debug probe is attached.
+
+use core::{fmt::Write, panic::PanicInfo};
+
+use common::CrashReport;
+use esp_hal::{ram, Persistable};
+
+/// Marks a record that was written by the panic handler and not yet taken.
+const MAGIC: u32 = 0xDEAD_C0DE;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Record {
+    magic: u32,
+    line: u32,
+    column: u32,
+    message_len: u32,
+    file_len: u32,
+    message: [u8; CrashReport::MESSAGE_LEN],
+    file: [u8; CrashReport::FILE_LEN],
+    checksum: u32,
+}
+
+// SAFETY: Only integers and byte arrays, any bit pattern is a valid (if not meaningful) record
+unsafe impl Persistable for Record {}
+
+impl Record {
+    const EMPTY: Self = Self {
+        magic: 0,
+        line: 0,
+        column: 0,
+        message_len: 0,
+        file_len: 0,
+        message: [0; CrashReport::MESSAGE_LEN],
+        file: [0; CrashReport::FILE_LEN],
+        checksum: 0,
+    };
+
+    fn checksum(&self) -> u32 {
+        let fields = [self.line, self.column, self.message_len, self.file_len];
+        fields
+            .iter()
+            .flat_map(|field| field.to_le_bytes())
+            .chain(self.message)
+            .chain(self.file)
+            .fold(MAGIC, |sum, byte| sum.rotate_left(5) ^ byte as u32)
+    }
+
+    /// Only a record written by the panic handler counts, not whatever was in RAM at power-on.
+    fn is_valid(&self) -> bool {
+        self.magic == MAGIC
+            && self.message_len as usize <= CrashReport::MESSAGE_LEN
+            && self.file_len as usize <= CrashReport::FILE_LEN
+            && self.checksum == self.checksum()
+    }
+}
+
+#[ram(rtc_fast, persistent)]
+static mut RECORD: Record = Record::EMPTY;
+
+/// Writes as much as fits into a fixed buffer and silently drops the rest.
+struct Truncating<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for Truncating<'_> {
+    fn write_str(&mut self, text: &str) -> core::fmt::Result {
+        for &byte in text.as_bytes() {
+            if self.len == self.buffer.len() {
+                break;
+            }
+            self.buffer[self.len] = byte;
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    defmt::error!("{}", defmt::Display2Format(info));
+
+    let mut record = Record::EMPTY;
+
+    let mut message = Truncating {
+        buffer: &mut record.message,
+        len: 0,
+    };
+    _ = write!(message, "{}", info.message());
+    record.message_len = message.len as u32;
+
+    if let Some(location) = info.location() {
+        let mut file = Truncating {
+            buffer: &mut record.file,
+            len: 0,
+        };
+        _ = file.write_str(location.file());
+        record.file_len = file.len as u32;
+        record.line = location.line();
+        record.column = location.column();
+    }
+
+    record.magic = MAGIC;
+    record.checksum = record.checksum();
+    // SAFETY: We are panicking, nobody else is running anymore
+    unsafe { (&raw mut RECORD).write_volatile(record) };
+
+    esp_hal::system::software_reset()
+}
+
+/// The crash report from before the last reset, if there was a crash. Only returned once.
+pub fn take() -> Option<CrashReport> {
+    // SAFETY: Only called from the main task, the panic handler never returns
+    let record = unsafe { (&raw mut RECORD).replace(Record::EMPTY) };
+    if !record.is_valid() {
+        return None;
+    }
+
+    Some(CrashReport::new(
+        text(&record.message[..record.message_len as usize]),
+        text(&record.file[..record.file_len as usize]),
+        record.line,
+        record.column,
+    ))
+}
+
+/// Truncation might have cut through a multi byte character, keep what's valid.
+fn text(bytes: &[u8]) -> &str {
+    match core::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(error) => core::str::from_utf8(&bytes[..error.valid_up_to()]).unwrap_or_default(),
+    }
+}
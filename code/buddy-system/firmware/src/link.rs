@@ -0,0 +1,109 @@
+//! The link to the host: an outbound queue drained by a dedicated USB serial TX task, and an RX
+//! task keeping track of whether the host is still there.
+//!
+//! Other tasks never wait on the host, see [`Outbox`].
+
+use common::{
+    link::{LinkEvent, LinkMonitor, HEARTBEAT_INTERVAL_MS, LINK_TIMEOUT_MS},
+    serial::{MessageReader, Outbox},
+    Message,
+};
+use defmt::{debug, info, warn};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{with_deadline, Instant, Timer};
+use embedded_io_async::{Read, Write};
+use esp_hal::{
+    usb_serial_jtag::{UsbSerialJtagRx, UsbSerialJtagTx},
+    Async,
+};
+use heapless::Vec;
+
+use crate::watchdog;
+
+/// How many messages can wait for the TX task.
+const QUEUE_DEPTH: usize = 8;
+
+static OUTBOX: Outbox<QUEUE_DEPTH> = Outbox::new();
+
+/// Changes of the host being alive (`true`) or lost (`false`), published by [`rx_task`].
+pub static HOST_ALIVE: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// Queue a message for the host, dropping the oldest queued one if the queue is full.
+pub fn send(message: Message) {
+    OUTBOX.send(message);
+}
+
+#[embassy_executor::task]
+pub async fn tx_task(mut usb_tx: UsbSerialJtagTx<'static, Async>) {
+    loop {
+        let message = OUTBOX.receive().await;
+        write(&mut usb_tx, &message).await;
+
+        if let Some((new, report)) = OUTBOX.take_drop_report() {
+            warn!("dropped {} messages for the host", new);
+            write(&mut usb_tx, &report).await;
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn heartbeat_task() {
+    loop {
+        Timer::after_millis(HEARTBEAT_INTERVAL_MS).await;
+        watchdog::check_in(watchdog::Task::Heartbeat);
+        // Any queued message tells the host we are alive just as well
+        if OUTBOX.is_empty() {
+            send(Message::Heartbeat);
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn rx_task(mut usb_rx: UsbSerialJtagRx<'static, Async>) {
+    let mut monitor = LinkMonitor::new(LINK_TIMEOUT_MS);
+    let mut reader = MessageReader::<128>::new();
+    let mut buffer = [0u8; 64];
+
+    loop {
+        handle_link_event(monitor.poll(Instant::now().as_millis()));
+
+        let deadline = monitor
+            .next_deadline()
+            .map(Instant::from_millis)
+            .unwrap_or(Instant::MAX)
+            .min(Instant::now() + watchdog::CHECK_IN_INTERVAL);
+        let read = with_deadline(deadline, usb_rx.read(&mut buffer)).await;
+        watchdog::check_in(watchdog::Task::HostRx);
+        let Ok(Ok(count)) = read else {
+            continue;
+        };
+
+        reader.feed(&buffer[..count], |message| match message {
+            Ok(message) => {
+                debug!("received {}", message);
+                handle_link_event(monitor.on_message(Instant::now().as_millis()));
+            }
+            Err(_) => warn!("failed to decode message from the host"),
+        });
+    }
+}
+
+fn handle_link_event(event: Option<LinkEvent>) {
+    match event {
+        Some(LinkEvent::Alive) => {
+            info!("host is alive");
+            HOST_ALIVE.signal(true);
+        }
+        Some(LinkEvent::Lost) => {
+            warn!("host is gone");
+            HOST_ALIVE.signal(false);
+        }
+        None => {}
+    }
+}
+
+async fn write(usb_tx: &mut UsbSerialJtagTx<'static, Async>, message: &Message) {
+    let frame: Vec<u8, 256> = postcard::to_vec_cobs(message).expect("Couldn't serialize message");
+    _ = usb_tx.write_all(&frame).await;
+    _ = usb_tx.flush().await;
+}
@@ -1,57 +1,140 @@
 #![no_std]
 #![no_main]
 
+mod crash;
+mod link;
+mod watchdog;
+
+use button::{Button, Edge, GestureConfig};
 use common::Message;
-use defmt::info;
+use defmt::{info, warn};
 use embassy_executor::Spawner;
-use embassy_time::{Duration, Timer};
+use embassy_time::{with_deadline, Duration, Instant};
 use esp_hal::{
-    gpio::{Input, InputConfig, Pull},
+    gpio::{Input, InputConfig, Level, Output, OutputConfig, Pull},
+    rtc_cntl::Rtc,
     timer::systimer::SystemTimer,
-    usb_serial_jtag::{UsbSerialJtag, UsbSerialJtagTx},
-    Blocking, Config,
+    usb_serial_jtag::UsbSerialJtag,
+    Config,
 };
 use esp_hal_embassy::main;
-use heapless::Vec;
-use panic_rtt_target as _;
+
+/// How long the button level has to stay unchanged before a press or release is accepted.
+const DEBOUNCE: Duration = Duration::from_millis(20);
+
+/// Where the outputs are driven to while the host is gone, and right after boot.
+struct SafeState {
+    status_led: Level,
+}
+
+const SAFE_STATE: SafeState = SafeState {
+    status_led: Level::Low,
+};
+
+/// Everything the firmware drives on behalf of the host.
+struct Outputs {
+    status_led: Output<'static>,
+}
+
+impl Outputs {
+    fn enter_safe_state(&mut self, safe_state: &SafeState) {
+        self.status_led.set_level(safe_state.status_led);
+    }
+}
 
 #[main]
-async fn main(_spawner: Spawner) {
+async fn main(spawner: Spawner) {
     rtt_target::rtt_init_defmt!();
 
     let peripherals = esp_hal::init(Config::default());
     let timer0 = SystemTimer::new(peripherals.SYSTIMER);
     esp_hal_embassy::init(timer0.alarm0);
 
-    info!("buddy");
+    let reset_reason = watchdog::reset_reason();
+    info!("buddy, reset reason: {}", reset_reason);
+    link::send(Message::Boot(reset_reason));
+    if let Some(report) = crash::take() {
+        warn!("crashed before the reset: {}", report);
+        link::send(Message::CrashReport(report));
+    }
+
+    spawner
+        .spawn(watchdog::supervisor_task(Rtc::new(peripherals.LPWR)))
+        .expect("Could not spawn supervisor task");
+
+    let usb_serial = UsbSerialJtag::new(peripherals.USB_DEVICE).into_async();
+    let (usb_rx, usb_tx) = usb_serial.split();
+
+    spawner
+        .spawn(link::tx_task(usb_tx))
+        .expect("Could not spawn TX task");
+    spawner
+        .spawn(link::rx_task(usb_rx))
+        .expect("Could not spawn RX task");
+    spawner
+        .spawn(link::heartbeat_task())
+        .expect("Could not spawn heartbeat task");
 
-    let usb_serial = UsbSerialJtag::new(peripherals.USB_DEVICE);
-    let (mut _usb_rx, mut usb_tx) = usb_serial.split();
+    // GPIO 10 is labeled D10 on the Seed Xiao board
+    let status_led = Output::new(
+        peripherals.GPIO10,
+        SAFE_STATE.status_led,
+        OutputConfig::default(),
+    );
+    spawner
+        .spawn(outputs_task(Outputs { status_led }))
+        .expect("Could not spawn outputs task");
 
     let config = InputConfig::default().with_pull(Pull::Up);
     let button = Input::new(peripherals.GPIO9, config);
-    let mut button_state = false;
+
+    spawner
+        .spawn(button_task(button, DEBOUNCE))
+        .expect("Could not spawn button task");
+}
+
+#[embassy_executor::task]
+async fn button_task(mut button: Input<'static>, debounce: Duration) {
+    let mut debounced = Button::new(debounce.as_millis(), GestureConfig::default());
 
     loop {
-        if !button_state && button.is_low() {
-            // button pressed
-            info!("button pressed");
-            button_state = true;
-            send_state(&mut usb_tx, button_state);
-        } else if button_state && button.is_high() {
-            // button released
-            info!("button released");
-            button_state = false;
-            send_state(&mut usb_tx, button_state);
-        }
+        // Sleep until the pin changes, the debouncer or a gesture needs the time to move on,
+        // or it's time to check in with the watchdog
+        let deadline = debounced
+            .next_deadline()
+            .map(Instant::from_millis)
+            .unwrap_or(Instant::MAX)
+            .min(Instant::now() + watchdog::CHECK_IN_INTERVAL);
+        let events = match with_deadline(deadline, button.wait_for_any_edge()).await {
+            Ok(()) => debounced.update(button.is_low(), Instant::now().as_millis()),
+            Err(_) => debounced.poll(Instant::now().as_millis()),
+        };
+        watchdog::check_in(watchdog::Task::Button);
 
-        Timer::after(Duration::from_millis(10)).await;
+        match events.edge {
+            Some(Edge::Pressed) => {
+                info!("button pressed");
+                link::send(Message::Button(true));
+            }
+            Some(Edge::Released) => {
+                info!("button released");
+                link::send(Message::Button(false));
+            }
+            None => {}
+        }
+        if let Some(gesture) = events.gesture {
+            info!("gesture: {}", gesture);
+        }
     }
 }
 
-fn send_state(usb_tx: &mut UsbSerialJtagTx<Blocking>, state: bool) {
-    let message: Vec<u8, 128> =
-        postcard::to_vec_cobs(&Message::Button(state)).expect("Couldn't serialize button state");
-    _ = usb_tx.write(&message);
-    _ = usb_tx.flush_tx();
+#[embassy_executor::task]
+async fn outputs_task(mut outputs: Outputs) {
+    loop {
+        if link::HOST_ALIVE.wait().await {
+            outputs.status_led.set_high();
+        } else {
+            outputs.enter_safe_state(&SAFE_STATE);
+        }
+    }
 }
@@ -0,0 +1,90 @@
+//! Hardware watchdog supervision of the firmware tasks.
+//!
+//! Every registered [`Task`] has to [`check_in`] at least every [`CHECK_IN_INTERVAL`]. The
+//! supervisor only feeds the RTC watchdog (RWDT) when all of them did, so a single wedged task
+//! resets the chip. The reset reason is reported to the host after the next boot.
+//!
+//! The USB TX task is not registered on purpose: it legitimately waits as long as the host
+//! does not read.
+
+use core::cell::Cell;
+
+use common::ResetReason;
+use critical_section::Mutex;
+use defmt::{warn, Format};
+use embassy_time::{Duration, Timer};
+use esp_hal::{
+    rtc_cntl::{Rtc, RwdtStage, SocResetReason},
+    system,
+};
+
+/// How often every registered task has to check in at least.
+pub const CHECK_IN_INTERVAL: Duration = Duration::from_millis(1_000);
+
+/// Without food for this long, the watchdog resets the chip.
+const WATCHDOG_TIMEOUT: esp_hal::time::Duration = esp_hal::time::Duration::from_secs(5);
+
+/// The tasks under supervision.
+#[derive(Debug, Clone, Copy, Format)]
+pub enum Task {
+    Button,
+    HostRx,
+    Heartbeat,
+}
+
+const ALL_TASKS: u32 =
+    (1 << Task::Button as u32) | (1 << Task::HostRx as u32) | (1 << Task::Heartbeat as u32);
+
+/// One bit per [`Task`] that checked in since the supervisor last looked.
+static CHECKED_IN: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Tell the supervisor the calling task is still making progress.
+pub fn check_in(task: Task) {
+    critical_section::with(|cs| {
+        let checked_in = CHECKED_IN.borrow(cs);
+        checked_in.set(checked_in.get() | 1 << task as u32);
+    });
+}
+
+#[embassy_executor::task]
+pub async fn supervisor_task(mut rtc: Rtc<'static>) {
+    rtc.rwdt.set_timeout(RwdtStage::Stage0, WATCHDOG_TIMEOUT);
+    rtc.rwdt.enable();
+
+    loop {
+        // Give every task the chance to check in at least once
+        Timer::after(CHECK_IN_INTERVAL * 2).await;
+
+        let checked_in = critical_section::with(|cs| CHECKED_IN.borrow(cs).replace(0));
+        if checked_in == ALL_TASKS {
+            rtc.rwdt.feed();
+        } else {
+            warn!(
+                "tasks missed their check-in, not feeding the watchdog: {=u32:#b}",
+                ALL_TASKS & !checked_in
+            );
+        }
+    }
+}
+
+/// Why the chip came out of reset this time.
+pub fn reset_reason() -> ResetReason {
+    match system::reset_reason() {
+        Some(SocResetReason::ChipPowerOn) => ResetReason::PowerOn,
+        Some(SocResetReason::CoreSw | SocResetReason::Cpu0Sw) => ResetReason::Software,
+        Some(
+            SocResetReason::CoreMwdt0
+            | SocResetReason::CoreMwdt1
+            | SocResetReason::CoreRtcWdt
+            | SocResetReason::Cpu0Mwdt0
+            | SocResetReason::Cpu0Mwdt1
+            | SocResetReason::Cpu0RtcWdt
+            | SocResetReason::SysRtcWdt
+            | SocResetReason::SysSuperWdt,
+        ) => ResetReason::Watchdog,
+        Some(SocResetReason::SysBrownOut) => ResetReason::BrownOut,
+        Some(SocResetReason::CoreUsbUart | SocResetReason::CoreUsbJtag) => ResetReason::Usb,
+        Some(other) => ResetReason::Other(other as u8),
+        None => ResetReason::Unknown,
+    }
+}
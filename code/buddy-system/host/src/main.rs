@@ -1,13 +1,24 @@
 use std::{
+    env,
     error::Error,
-    io::{BufRead, BufReader},
-    time::Duration,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    thread,
+    time::{Duration, Instant},
 };
 
-use common::Message;
+use common::{
+    Message, ResetReason, SensorReading,
+    link::{HEARTBEAT_INTERVAL_MS, LINK_TIMEOUT_MS, LinkEvent, LinkMonitor},
+};
 use serialport::SerialPortType;
 
+/// Usage: `host [recording.csv]`, sensor readings are appended to the file if one is given.
+///
+/// Typing `recalibrate` makes the IMU calibrate again, with the sensor lying flat and still.
 fn main() -> Result<(), Box<dyn Error>> {
+    let mut recording = env::args().nth(1).map(open_recording).transpose()?;
+
     let ports = serialport::available_ports().expect("No ports found");
 
     println!("{} ports available", ports.len());
@@ -25,21 +36,148 @@ fn main() -> Result<(), Box<dyn Error>> {
                 port.port_name
             );
 
-            let mut port = serialport::new(port.port_name, 115_200)
-                .timeout(Duration::MAX)
+            // Reads time out regularly so we notice a silent device even with the port open
+            let port = serialport::new(port.port_name, 115_200)
+                .timeout(Duration::from_millis(100))
                 .open()?;
+
+            let mut heartbeat_port = port.try_clone()?;
+            thread::spawn(move || {
+                let mut frame = [0u8; 16];
+                let heartbeat = postcard::to_slice_cobs(&Message::Heartbeat, &mut frame)
+                    .expect("Couldn't serialize heartbeat");
+                while heartbeat_port.write_all(heartbeat).is_ok() {
+                    thread::sleep(Duration::from_millis(HEARTBEAT_INTERVAL_MS));
+                }
+            });
+
+            let mut command_port = port.try_clone()?;
+            thread::spawn(move || {
+                let mut frame = [0u8; 16];
+                let recalibrate = postcard::to_slice_cobs(&Message::Recalibrate, &mut frame)
+                    .expect("Couldn't serialize recalibrate");
+                for line in io::stdin().lines().map_while(Result::ok) {
+                    match line.trim() {
+                        "recalibrate" => {
+                            if command_port.write_all(recalibrate).is_err() {
+                                break;
+                            }
+                            println!("Asked the device to recalibrate");
+                        }
+                        "" => {}
+                        command => println!("Unknown command {command:?}, try `recalibrate`"),
+                    }
+                }
+            });
+
+            let start = Instant::now();
+            let mut monitor = LinkMonitor::new(LINK_TIMEOUT_MS);
+            let mut reader = BufReader::new(port);
+            let mut buffer = Vec::new();
             loop {
-                let mut reader = BufReader::new(&mut port);
-                let mut buffer = Vec::new();
-                reader.read_until(0x00, &mut buffer)?;
-                if let Ok(message) = postcard::from_bytes_cobs::<Message>(&mut buffer) {
-                    println!("{message:?} ({buffer:?})");
-                } else {
-                    println!("Failed to decode message");
+                match reader.read_until(0x00, &mut buffer) {
+                    Ok(_) => {
+                        let event = monitor.on_message(start.elapsed().as_millis() as u64);
+                        report_link_event(event);
+
+                        match postcard::from_bytes_cobs::<Message>(&mut buffer) {
+                            Ok(Message::Heartbeat) => {}
+                            Ok(Message::Dropped(count)) => {
+                                println!("Device dropped {count} messages so far");
+                            }
+                            Ok(Message::Boot(ResetReason::Watchdog)) => {
+                                println!("Device was reset by its watchdog!");
+                            }
+                            Ok(Message::Boot(reason)) => {
+                                println!("Device booted, reset reason: {reason:?}");
+                            }
+                            Ok(Message::SensorReading(reading)) => {
+                                display_reading(&reading);
+                                if let Some(file) = &mut recording {
+                                    record_reading(file, start.elapsed(), &reading)?;
+                                }
+                            }
+                            Ok(Message::FifoOverflow(count)) => {
+                                println!("IMU FIFO overflowed {count} times so far");
+                            }
+                            Ok(Message::Orientation(orientation)) => println!(
+                                "Orientation: roll {:6.1}°, pitch {:6.1}°, yaw {:6.1}°",
+                                orientation.roll, orientation.pitch, orientation.yaw
+                            ),
+                            Ok(Message::CrashReport(report)) => {
+                                eprintln!("!!!!!!!! DEVICE CRASHED BEFORE ITS LAST RESET !!!!!!!!");
+                                eprintln!(
+                                    "!!! panicked at {}:{}:{}",
+                                    report.file, report.line, report.column
+                                );
+                                eprintln!("!!! {}", report.message);
+                            }
+                            Ok(message) => println!("{message:?} ({buffer:?})"),
+                            Err(_) => println!("Failed to decode message"),
+                        }
+                        buffer.clear();
+                    }
+                    // Partial frames stay in `buffer` and are completed by the next read
+                    Err(error) if error.kind() == io::ErrorKind::TimedOut => {}
+                    Err(error) => return Err(error.into()),
                 }
+
+                report_link_event(monitor.poll(start.elapsed().as_millis() as u64));
             }
         }
     }
 
     Ok(())
 }
+
+fn report_link_event(event: Option<LinkEvent>) {
+    match event {
+        Some(LinkEvent::Alive) => println!("Device is alive"),
+        Some(LinkEvent::Lost) => {
+            println!("Device silent for more than {LINK_TIMEOUT_MS} ms!");
+        }
+        None => {}
+    }
+}
+
+fn display_reading(reading: &SensorReading) {
+    match protocol::sensor_info(reading.sensor_id) {
+        Some(info) => println!(
+            "{} {}: {:.*} {} [timestamp: {}]",
+            info.icon,
+            info.name,
+            info.precision as usize,
+            reading.value,
+            info.unit,
+            reading.timestamp
+        ),
+        None => println!(
+            "Unknown sensor {}: {:.2} [timestamp: {}]",
+            reading.sensor_id, reading.value, reading.timestamp
+        ),
+    }
+}
+
+/// Opens the CSV file for appending, new files get a header first.
+fn open_recording(path: String) -> io::Result<File> {
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "host_ms,sensor_id,sensor,value,unit,timestamp")?;
+    }
+    println!("Recording sensor readings to {path}");
+    Ok(file)
+}
+
+fn record_reading(file: &mut File, elapsed: Duration, reading: &SensorReading) -> io::Result<()> {
+    let info = protocol::sensor_info(reading.sensor_id);
+    writeln!(
+        file,
+        "{},{},{},{},{},{}",
+        elapsed.as_millis(),
+        reading.sensor_id,
+        info.map_or("unknown", |info| info.name),
+        reading.value,
+        info.map_or("", |info| info.unit),
+        reading.timestamp
+    )
+}
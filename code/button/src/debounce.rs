@@ -0,0 +1,107 @@
+/// A debounced change of the button state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Edge {
+    Pressed,
+    Released,
+}
+
+/// Accepts a new button level only once it stayed the same for the whole debounce window.
+///
+/// Works with both periodic sampling ([`Debouncer::update`] every few milliseconds) and edge
+/// interrupts ([`Debouncer::update`] on every edge, [`Debouncer::poll`] once the window passed).
+pub struct Debouncer {
+    window_ms: u64,
+    stable: bool,
+    candidate: bool,
+    candidate_since: u64,
+}
+
+impl Debouncer {
+    /// A debouncer for a button that is released at start-up.
+    pub const fn new(window_ms: u64) -> Self {
+        Self {
+            window_ms,
+            stable: false,
+            candidate: false,
+            candidate_since: 0,
+        }
+    }
+
+    /// Feed a raw sample (`true` = pressed) taken at `now_ms`.
+    pub fn update(&mut self, pressed: bool, now_ms: u64) -> Option<Edge> {
+        if pressed != self.candidate {
+            self.candidate = pressed;
+            self.candidate_since = now_ms;
+        }
+        self.poll(now_ms)
+    }
+
+    /// Check whether the last sample has settled by now.
+    pub fn poll(&mut self, now_ms: u64) -> Option<Edge> {
+        if self.candidate == self.stable
+            || now_ms.saturating_sub(self.candidate_since) < self.window_ms
+        {
+            return None;
+        }
+
+        self.stable = self.candidate;
+        Some(if self.stable {
+            Edge::Pressed
+        } else {
+            Edge::Released
+        })
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        self.stable
+    }
+
+    /// When the pending level change will be accepted, if there is one.
+    pub fn next_deadline(&self) -> Option<u64> {
+        (self.candidate != self.stable).then_some(self.candidate_since + self.window_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_level_after_window() {
+        let mut debouncer = Debouncer::new(20);
+
+        assert_eq!(debouncer.update(true, 100), None);
+        assert_eq!(debouncer.update(true, 119), None);
+        assert_eq!(debouncer.update(true, 120), Some(Edge::Pressed));
+        assert_eq!(debouncer.update(true, 130), None);
+        assert!(debouncer.is_pressed());
+
+        assert_eq!(debouncer.update(false, 200), None);
+        assert_eq!(debouncer.poll(220), Some(Edge::Released));
+        assert!(!debouncer.is_pressed());
+    }
+
+    #[test]
+    fn bouncing_restarts_window() {
+        let mut debouncer = Debouncer::new(20);
+
+        for now in (0..100).step_by(5) {
+            assert_eq!(debouncer.update(now % 10 == 0, now), None);
+        }
+        assert_eq!(debouncer.update(true, 100), None);
+        assert_eq!(debouncer.next_deadline(), Some(120));
+        assert_eq!(debouncer.poll(120), Some(Edge::Pressed));
+    }
+
+    #[test]
+    fn short_glitch_is_ignored() {
+        let mut debouncer = Debouncer::new(20);
+
+        assert_eq!(debouncer.update(true, 0), None);
+        assert_eq!(debouncer.update(false, 3), None);
+        assert_eq!(debouncer.next_deadline(), None);
+        assert_eq!(debouncer.poll(1000), None);
+        assert!(!debouncer.is_pressed());
+    }
+}
@@ -0,0 +1,264 @@
+use crate::Edge;
+
+/// Higher level interpretation of debounced presses and releases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Gesture {
+    /// A short press that was not followed by a second one.
+    Click,
+    /// Two short presses within [`GestureConfig::double_click_ms`].
+    DoubleClick,
+    /// The button is held down for [`GestureConfig::long_press_ms`].
+    LongPress,
+    /// The button is still held after a long press, repeated every
+    /// [`GestureConfig::repeat_interval_ms`]. Counts up from 1.
+    HoldRepeat(u32),
+}
+
+/// Timings of the gestures, all in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GestureConfig {
+    /// Maximum time between releasing the first and pressing the second click.
+    /// With `0` every short press is a [`Gesture::Click`] right away.
+    pub double_click_ms: u64,
+    /// How long the button has to be held for a [`Gesture::LongPress`].
+    pub long_press_ms: u64,
+    /// Period of [`Gesture::HoldRepeat`] after a long press. `None` disables repeating.
+    pub repeat_interval_ms: Option<u64>,
+}
+
+impl GestureConfig {
+    pub const fn new() -> Self {
+        Self {
+            double_click_ms: 250,
+            long_press_ms: 800,
+            repeat_interval_ms: Some(200),
+        }
+    }
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Idle,
+    /// Button is down. `second` is set when this press may become a double click.
+    Down {
+        since: u64,
+        second: bool,
+    },
+    /// A long press was reported, the button is still down.
+    Held {
+        next_repeat: u64,
+        repeats: u32,
+    },
+    /// One click is done, a second press would make it a double click.
+    WaitingForSecond {
+        released_at: u64,
+    },
+}
+
+/// Turns debounced [`Edge`]s into [`Gesture`]s.
+///
+/// Long presses and the end of the double click window are detected by time, so
+/// [`GestureDetector::poll`] has to be called regularly or at [`GestureDetector::next_deadline`].
+pub struct GestureDetector {
+    config: GestureConfig,
+    state: State,
+}
+
+impl GestureDetector {
+    pub const fn new(config: GestureConfig) -> Self {
+        Self {
+            config,
+            state: State::Idle,
+        }
+    }
+
+    pub fn on_edge(&mut self, edge: Edge, now_ms: u64) -> Option<Gesture> {
+        // A late call must not turn an expired click into a double click
+        let expired = self.poll(now_ms);
+
+        match (edge, self.state) {
+            (Edge::Pressed, State::WaitingForSecond { .. }) => {
+                self.state = State::Down {
+                    since: now_ms,
+                    second: true,
+                };
+                None
+            }
+            (Edge::Pressed, _) => {
+                self.state = State::Down {
+                    since: now_ms,
+                    second: false,
+                };
+                expired
+            }
+            (Edge::Released, State::Down { second: true, .. }) => {
+                self.state = State::Idle;
+                Some(Gesture::DoubleClick)
+            }
+            (Edge::Released, State::Down { second: false, .. }) => {
+                if self.config.double_click_ms == 0 {
+                    self.state = State::Idle;
+                    Some(Gesture::Click)
+                } else {
+                    self.state = State::WaitingForSecond {
+                        released_at: now_ms,
+                    };
+                    None
+                }
+            }
+            (Edge::Released, _) => {
+                self.state = State::Idle;
+                expired
+            }
+        }
+    }
+
+    pub fn poll(&mut self, now_ms: u64) -> Option<Gesture> {
+        match self.state {
+            State::Down { since, .. } if now_ms >= since + self.config.long_press_ms => {
+                self.state = State::Held {
+                    next_repeat: self.first_repeat(since),
+                    repeats: 0,
+                };
+                Some(Gesture::LongPress)
+            }
+            State::Held {
+                next_repeat,
+                repeats,
+            } if now_ms >= next_repeat => {
+                let interval = self.config.repeat_interval_ms.unwrap_or(u64::MAX);
+                self.state = State::Held {
+                    next_repeat: next_repeat.saturating_add(interval),
+                    repeats: repeats + 1,
+                };
+                Some(Gesture::HoldRepeat(repeats + 1))
+            }
+            State::WaitingForSecond { released_at }
+                if now_ms >= released_at + self.config.double_click_ms =>
+            {
+                self.state = State::Idle;
+                Some(Gesture::Click)
+            }
+            _ => None,
+        }
+    }
+
+    /// When the next time based gesture is due, if any.
+    pub fn next_deadline(&self) -> Option<u64> {
+        match self.state {
+            State::Idle => None,
+            State::Down { since, .. } => Some(since + self.config.long_press_ms),
+            State::Held { next_repeat, .. } => self.config.repeat_interval_ms.map(|_| next_repeat),
+            State::WaitingForSecond { released_at } => {
+                Some(released_at + self.config.double_click_ms)
+            }
+        }
+    }
+
+    fn first_repeat(&self, since: u64) -> u64 {
+        match self.config.repeat_interval_ms {
+            Some(interval) => since + self.config.long_press_ms + interval,
+            None => u64::MAX,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector() -> GestureDetector {
+        GestureDetector::new(GestureConfig {
+            double_click_ms: 200,
+            long_press_ms: 1000,
+            repeat_interval_ms: Some(100),
+        })
+    }
+
+    #[test]
+    fn click_after_double_click_window() {
+        let mut gestures = detector();
+
+        assert_eq!(gestures.on_edge(Edge::Pressed, 0), None);
+        assert_eq!(gestures.on_edge(Edge::Released, 100), None);
+        assert_eq!(gestures.poll(299), None);
+        assert_eq!(gestures.next_deadline(), Some(300));
+        assert_eq!(gestures.poll(300), Some(Gesture::Click));
+        assert_eq!(gestures.poll(1000), None);
+    }
+
+    #[test]
+    fn click_without_double_click_window() {
+        let mut gestures = GestureDetector::new(GestureConfig {
+            double_click_ms: 0,
+            ..GestureConfig::default()
+        });
+
+        assert_eq!(gestures.on_edge(Edge::Pressed, 0), None);
+        assert_eq!(gestures.on_edge(Edge::Released, 100), Some(Gesture::Click));
+        assert_eq!(gestures.next_deadline(), None);
+    }
+
+    #[test]
+    fn double_click() {
+        let mut gestures = detector();
+
+        assert_eq!(gestures.on_edge(Edge::Pressed, 0), None);
+        assert_eq!(gestures.on_edge(Edge::Released, 100), None);
+        assert_eq!(gestures.on_edge(Edge::Pressed, 250), None);
+        assert_eq!(
+            gestures.on_edge(Edge::Released, 350),
+            Some(Gesture::DoubleClick)
+        );
+        assert_eq!(gestures.poll(2000), None);
+    }
+
+    #[test]
+    fn late_second_press_is_a_new_click() {
+        let mut gestures = detector();
+
+        gestures.on_edge(Edge::Pressed, 0);
+        gestures.on_edge(Edge::Released, 100);
+        // Nobody polled in between, the first click still has to come out
+        assert_eq!(gestures.on_edge(Edge::Pressed, 500), Some(Gesture::Click));
+        assert_eq!(gestures.on_edge(Edge::Released, 600), None);
+        assert_eq!(gestures.poll(800), Some(Gesture::Click));
+    }
+
+    #[test]
+    fn long_press_with_hold_repeat() {
+        let mut gestures = detector();
+
+        gestures.on_edge(Edge::Pressed, 0);
+        assert_eq!(gestures.poll(999), None);
+        assert_eq!(gestures.poll(1000), Some(Gesture::LongPress));
+        assert_eq!(gestures.poll(1050), None);
+        assert_eq!(gestures.poll(1100), Some(Gesture::HoldRepeat(1)));
+        assert_eq!(gestures.poll(1200), Some(Gesture::HoldRepeat(2)));
+        assert_eq!(gestures.next_deadline(), Some(1300));
+
+        // Releasing after a long press is not a click
+        assert_eq!(gestures.on_edge(Edge::Released, 1250), None);
+        assert_eq!(gestures.poll(5000), None);
+    }
+
+    #[test]
+    fn long_press_without_repeat() {
+        let mut gestures = GestureDetector::new(GestureConfig {
+            repeat_interval_ms: None,
+            ..GestureConfig::default()
+        });
+
+        gestures.on_edge(Edge::Pressed, 0);
+        assert_eq!(gestures.poll(800), Some(Gesture::LongPress));
+        assert_eq!(gestures.next_deadline(), None);
+        assert_eq!(gestures.poll(100_000), None);
+    }
+}
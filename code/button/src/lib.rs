@@ -0,0 +1,120 @@
+//! Hardware independent button handling: debouncing and gesture detection.
+//!
+//! Nothing in here touches a GPIO or a timer. You feed in the raw button level together with a
+//! timestamp in milliseconds and get back clean presses/releases and gestures. This way the
+//! same logic works with polling, interrupts or embassy tasks, and can be tested on the host.
+
+#![no_std]
+
+mod debounce;
+mod gesture;
+
+pub use debounce::{Debouncer, Edge};
+pub use gesture::{Gesture, GestureConfig, GestureDetector};
+
+/// Everything that happened during one [`Button::update`] or [`Button::poll`] call.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Events {
+    pub edge: Option<Edge>,
+    pub gesture: Option<Gesture>,
+}
+
+/// A [`Debouncer`] and a [`GestureDetector`] wired together.
+pub struct Button {
+    debouncer: Debouncer,
+    gestures: GestureDetector,
+}
+
+impl Button {
+    pub const fn new(debounce_ms: u64, config: GestureConfig) -> Self {
+        Self {
+            debouncer: Debouncer::new(debounce_ms),
+            gestures: GestureDetector::new(config),
+        }
+    }
+
+    /// Feed a raw sample of the button (`true` = pressed) taken at `now_ms`.
+    pub fn update(&mut self, pressed: bool, now_ms: u64) -> Events {
+        let edge = self.debouncer.update(pressed, now_ms);
+        self.handle(edge, now_ms)
+    }
+
+    /// Let time pass without a new sample, e.g. when waiting for edge interrupts.
+    pub fn poll(&mut self, now_ms: u64) -> Events {
+        let edge = self.debouncer.poll(now_ms);
+        self.handle(edge, now_ms)
+    }
+
+    /// The debounced state of the button.
+    pub fn is_pressed(&self) -> bool {
+        self.debouncer.is_pressed()
+    }
+
+    /// When [`Button::poll`] has to be called next at the latest, if at all.
+    ///
+    /// Without any further edge on the pin nothing can happen before this point in time.
+    pub fn next_deadline(&self) -> Option<u64> {
+        match (
+            self.debouncer.next_deadline(),
+            self.gestures.next_deadline(),
+        ) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn handle(&mut self, edge: Option<Edge>, now_ms: u64) -> Events {
+        let gesture = match edge {
+            Some(edge) => self.gestures.on_edge(edge, now_ms),
+            None => self.gestures.poll(now_ms),
+        };
+        Events { edge, gesture }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bouncy_click_is_reported_once() {
+        let mut button = Button::new(20, GestureConfig::default());
+        let mut edges = 0;
+        let mut gestures = 0;
+
+        // Contacts bounce for a couple of milliseconds on press and release
+        let samples = [
+            (0, true),
+            (2, false),
+            (4, true),
+            (30, true),
+            (100, false),
+            (101, true),
+            (103, false),
+            (130, false),
+        ];
+        for (now, pressed) in samples {
+            let events = button.update(pressed, now);
+            edges += events.edge.is_some() as u32;
+            gestures += events.gesture.is_some() as u32;
+        }
+        assert_eq!(edges, 2);
+        assert_eq!(gestures, 0);
+
+        // The click is only final once no second click can follow anymore
+        let deadline = button.next_deadline().unwrap();
+        assert_eq!(button.poll(deadline).gesture, Some(Gesture::Click));
+        assert_eq!(button.next_deadline(), None);
+    }
+
+    #[test]
+    fn edge_driven_input_settles_on_poll() {
+        let mut button = Button::new(20, GestureConfig::default());
+
+        assert_eq!(button.update(true, 0), Events::default());
+        assert_eq!(button.next_deadline(), Some(20));
+        assert_eq!(button.poll(20).edge, Some(Edge::Pressed));
+        assert!(button.is_pressed());
+    }
+}
@@ -6,8 +6,10 @@
     holding buffers for the duration of a data transfer."
 )]
 
+use button::{Button, Edge, GestureConfig};
 use defmt::info;
 use embassy_executor::Spawner;
+use embassy_time::{with_deadline, Instant};
 use esp_hal::clock::CpuClock;
 use esp_hal::gpio::{Input, InputConfig, Pull};
 use esp_hal::timer::systimer::SystemTimer;
@@ -47,10 +49,32 @@ async fn main(spawner: Spawner) {
 
 #[embassy_executor::task]
 async fn my_interrupt_awaiting_task(mut input_button: Input<'static>) {
+    let mut debounced = Button::new(20, GestureConfig::default());
+
     loop {
-        info!("Waiting for a button press");
-        // I.E.: When we press the button, the edge will fall
-        input_button.wait_for_falling_edge().await;
-        info!("I got woken up!")
+        // Sleep until the pin changes, or until the debouncer or a gesture needs the time to move on
+        let events = match debounced.next_deadline() {
+            Some(deadline) => {
+                let deadline = Instant::from_millis(deadline);
+                match with_deadline(deadline, input_button.wait_for_any_edge()).await {
+                    Ok(()) => debounced.update(input_button.is_low(), Instant::now().as_millis()),
+                    Err(_) => debounced.poll(Instant::now().as_millis()),
+                }
+            }
+            None => {
+                info!("Waiting for a button press");
+                input_button.wait_for_any_edge().await;
+                debounced.update(input_button.is_low(), Instant::now().as_millis())
+            }
+        };
+
+        match events.edge {
+            Some(Edge::Pressed) => info!("I got woken up by a press!"),
+            Some(Edge::Released) => info!("I got woken up by a release!"),
+            None => {}
+        }
+        if let Some(gesture) = events.gesture {
+            info!("Gesture: {}", gesture);
+        }
     }
 }
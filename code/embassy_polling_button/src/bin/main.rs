@@ -6,10 +6,11 @@
     holding buffers for the duration of a data transfer."
 )]
 
+use button::{Button, Edge, Gesture, GestureConfig};
 use defmt::{error, info};
 use embassy_executor::Spawner;
 use embassy_sync::channel::Channel;
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Instant, Timer};
 use esp_hal::clock::CpuClock;
 use esp_hal::gpio::{Input, InputConfig, Pull};
 use esp_hal::timer::systimer::SystemTimer;
@@ -35,33 +36,38 @@ static BUTTON_CHANNEL: Channel<
 enum ButtonEvent {
     Pressed,
     Released,
+    Gesture(Gesture),
 }
 
 // Button polling task
 #[embassy_executor::task]
 async fn button_task(button: Input<'static>) {
     let sender = BUTTON_CHANNEL.sender();
-    let mut last_state = button.is_high();
+    let mut debounced = Button::new(20, GestureConfig::default());
 
     info!("Button task started, monitoring GPIO9");
 
     loop {
-        let current_state = button.is_high();
+        let events = debounced.update(button.is_low(), Instant::now().as_millis());
 
-        // We only send an event, if the button state changed
-        if last_state != current_state {
-            if current_state {
-                info!("Button released");
-                sender.send(ButtonEvent::Released).await;
-            } else {
+        // We only send an event, if the debounced button state changed
+        match events.edge {
+            Some(Edge::Pressed) => {
                 info!("Button pressed");
                 sender.send(ButtonEvent::Pressed).await;
             }
-            last_state = current_state;
+            Some(Edge::Released) => {
+                info!("Button released");
+                sender.send(ButtonEvent::Released).await;
+            }
+            None => {}
+        }
+        if let Some(gesture) = events.gesture {
+            sender.send(ButtonEvent::Gesture(gesture)).await;
         }
 
-        // Small delay to debounce and avoid excessive polling
-        Timer::after(Duration::from_millis(50)).await;
+        // Sample often enough for the debouncer to see the contacts settle
+        Timer::after(Duration::from_millis(10)).await;
     }
 }
 
@@ -86,6 +92,9 @@ async fn button_handler_task() {
             ButtonEvent::Released => {
                 info!("🟢 Button handler: Received RELEASED event!");
             }
+            ButtonEvent::Gesture(gesture) => {
+                info!("👆 Button handler: Received gesture {}", gesture);
+            }
         }
     }
 }
@@ -46,8 +46,14 @@ fn linker_be_nice() {
         std::process::exit(0);
     }
 
+    // The Xtensa toolchain links through GCC, which wants linker arguments wrapped
+    let wrap = match std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() {
+        Ok("xtensa") => "-Wl,",
+        _ => "",
+    };
     println!(
-        "cargo:rustc-link-arg=--error-handling-script={}",
+        "cargo:rustc-link-arg={}--error-handling-script={}",
+        wrap,
         std::env::current_exe().unwrap().display()
     );
 }
@@ -0,0 +1,327 @@
+//! Mostly copied here: https://github.com/barafael/mpu6050-dmp-rs/blob/master/examples/src/basic_async.rs
+
+#![no_std]
+#![no_main]
+#![deny(
+    clippy::mem_forget,
+    reason = "mem::forget is generally not safe to do with esp_hal types, especially those \
+    holding buffers for the duration of a data transfer."
+)]
+
+use button::{Button, Gesture, GestureConfig};
+use common::link::HEARTBEAT_INTERVAL_MS;
+use common::orientation::{Decimator, Quaternion, DMP_PACKET_SIZE};
+use common::serial::{MessageReader, Outbox};
+use common::Message;
+use defmt::{error, info, warn};
+use embassy_executor::Spawner;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_sync::watch::Watch;
+use embassy_time::{with_deadline, with_timeout, Duration, Instant, Timer};
+use embedded_io_async::{Read, Write};
+use esp_hal::clock::CpuClock;
+use esp_hal::gpio::{Input, InputConfig, Pull};
+use esp_hal::i2c::master::{Config, I2c};
+use esp_hal::timer::systimer::SystemTimer;
+use esp_hal::usb_serial_jtag::{UsbSerialJtag, UsbSerialJtagRx, UsbSerialJtagTx};
+use esp_hal::Async;
+use i2c_imu::calibration::CalibrationStore;
+use i2c_imu::{Board, I2C_FREQUENCY};
+
+use embassy_time::Delay;
+use mpu6050_dmp::sensor_async::Mpu6050;
+use mpu6050_dmp::{address::Address, calibration::CalibrationParameters};
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    error!("{}", info);
+    loop {}
+}
+
+// This creates a default app-descriptor required by the esp-idf bootloader.
+// For more information see: <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/system/app_image_format.html#application-description>
+esp_bootloader_esp_idf::esp_app_desc!();
+
+/// The MPU6050's FIFO, packets are lost once it is full
+const FIFO_SIZE: usize = 1024;
+
+/// How often the orientation is sampled and sent to the host, from 1 Hz up to the DMP's 200 Hz
+const SAMPLE_RATE_HZ: u32 = 50;
+
+/// The DMP pulses the INT pin every 5 ms. Without an edge for this long the FIFO is read
+/// anyway, so a loose INT wire slows the samples down instead of overflowing the FIFO.
+const INT_TIMEOUT_MS: u64 = 50;
+
+/// INT_ENABLE register of the MPU6050
+const INT_ENABLE: u8 = 0x38;
+/// Interrupt for a FIFO overflow, in INT_ENABLE and INT_STATUS
+const INT_FIFO_OVERFLOW: u8 = 1 << 4;
+/// Interrupt for every packet of the DMP, not in the datasheet but in InvenSense's DMP driver
+const INT_DMP: u8 = 1 << 1;
+
+/// How many messages can wait for the link task: a heartbeat interval of samples, plus a few
+/// for the heartbeat and the odd report in between
+const QUEUE_DEPTH: usize = (HEARTBEAT_INTERVAL_MS * SAMPLE_RATE_HZ as u64 / 1000) as usize + 4;
+
+/// Tasks following the orientation: the log
+const ORIENTATION_RECEIVERS: usize = 1;
+
+/// How long the button level has to stay unchanged before a press or release is accepted
+const DEBOUNCE_MS: u64 = 20;
+
+/// Set by a long press of the button or the host, the sensor calibrates again
+static RECALIBRATE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Messages for the host, the IMU never waits on it
+static OUTBOX: Outbox<QUEUE_DEPTH> = Outbox::new();
+
+/// Latest orientation of the sensor, for any task that wants it
+static ORIENTATION: Watch<CriticalSectionRawMutex, Quaternion, ORIENTATION_RECEIVERS> =
+    Watch::new();
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) {
+    // generator version: 0.5.0
+
+    rtt_target::rtt_init_defmt!();
+
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let board = Board::new(esp_hal::init(config));
+
+    let timer0 = SystemTimer::new(board.systimer);
+    esp_hal_embassy::init(timer0.alarm0);
+
+    info!("Embassy initialized!");
+
+    let (usb_rx, usb_tx) = UsbSerialJtag::new(board.usb).into_async().split();
+    spawner.must_spawn(log_task());
+    spawner.must_spawn(link_task(usb_tx));
+    spawner.must_spawn(host_rx_task(usb_rx));
+
+    let button = Input::new(board.button, InputConfig::default().with_pull(Pull::Up));
+    spawner.must_spawn(button_task(button));
+
+    // Push-pull and active high, as the MPU6050 drives it after a reset
+    let mut int = Input::new(board.int, InputConfig::default());
+    let mut decimator = Decimator::new(SAMPLE_RATE_HZ).expect("Sample rate out of range");
+
+    let mut store = CalibrationStore::new().expect("No flash partition for the calibration");
+
+    info!("Init i2c");
+    let i2c_config = Config::default().with_frequency(I2C_FREQUENCY);
+    let i2c = I2c::new(board.i2c, i2c_config)
+        .expect("Failed to initialize I2C")
+        .with_sda(board.sda)
+        .with_scl(board.scl)
+        .into_async();
+
+    info!("Init i2c finished");
+
+    let mut sensor = Mpu6050::new(i2c, Address::default())
+        .await
+        .expect("Could not create MPU6050 Sensor");
+    info!("Init Sensor finished");
+
+    info!("Init DMP");
+    sensor.initialize_dmp(&mut Delay).await.unwrap();
+    let mut sensor = enable_interrupts(sensor).await;
+    info!("DMP finished");
+
+    match store.load().await {
+        Ok(Some((accel, gyro))) => {
+            info!("Using the stored calibration");
+            sensor.set_accel_calibration(&accel).await.unwrap();
+            sensor.set_gyro_calibration(&gyro).await.unwrap();
+        }
+        Ok(None) => calibrate(&mut sensor, &mut store).await,
+        Err(error) => {
+            warn!("Couldn't read the stored calibration: {}", error);
+            calibrate(&mut sensor, &mut store).await;
+        }
+    }
+
+    // Calibration read raw values, start over with nothing but DMP packets
+    restart_fifo(&mut sensor).await;
+
+    info!("Sampling at {} Hz", decimator.rate_hz());
+    let sender = ORIENTATION.sender();
+    let mut packet = [0u8; DMP_PACKET_SIZE];
+    let mut overflows = 0u32;
+    let mut int_silent = false;
+    loop {
+        if RECALIBRATE.try_take().is_some() {
+            calibrate(&mut sensor, &mut store).await;
+            restart_fifo(&mut sensor).await;
+        }
+
+        // An edge missed while reading only delays its packet, it waits in the FIFO
+        let edge = with_timeout(
+            Duration::from_millis(INT_TIMEOUT_MS),
+            int.wait_for_rising_edge(),
+        )
+        .await;
+        if edge.is_err() && !int_silent {
+            warn!("No interrupt from the MPU6050, is its INT pin connected?");
+        }
+        int_silent = edge.is_err();
+
+        let status = sensor.interrupt_read_clear().await.unwrap();
+        let count = sensor.get_fifo_count().await.unwrap();
+        if status & INT_FIFO_OVERFLOW != 0 || count >= FIFO_SIZE {
+            // Packets were lost and the rest may be out of step
+            overflows = overflows.wrapping_add(1);
+            warn!("DMP FIFO overflowed ({} times), resetting it", overflows);
+            OUTBOX.send(Message::FifoOverflow(overflows));
+            restart_fifo(&mut sensor).await;
+            continue;
+        }
+
+        for _ in 0..count / DMP_PACKET_SIZE {
+            sensor.read_fifo(&mut packet).await.unwrap();
+            if !decimator.on_packet() {
+                continue;
+            }
+            if let Some(quaternion) = Quaternion::from_dmp_packet(&packet) {
+                sender.send(quaternion);
+                OUTBOX.send(Message::Orientation(quaternion.to_euler()));
+            }
+        }
+    }
+}
+
+/// Has the INT pin pulse for every DMP packet and for a FIFO overflow.
+///
+/// `mpu6050-dmp` enables one interrupt at a time and has none for the DMP, so the register is
+/// written here with the driver's I2C bus borrowed for a moment.
+async fn enable_interrupts(sensor: Mpu6050<I2c<'static, Async>>) -> Mpu6050<I2c<'static, Async>> {
+    let mut i2c = sensor.release();
+    i2c.write_async(
+        u8::from(Address::default()),
+        &[INT_ENABLE, INT_DMP | INT_FIFO_OVERFLOW],
+    )
+    .await
+    .expect("Could not enable the MPU6050 interrupts");
+    Mpu6050::new(i2c, Address::default())
+        .await
+        .expect("Could not create MPU6050 Sensor")
+}
+
+/// Empties the FIFO and forgets an overflow that happened before
+async fn restart_fifo(sensor: &mut Mpu6050<I2c<'static, Async>>) {
+    sensor.reset_fifo().await.unwrap();
+    sensor.interrupt_read_clear().await.unwrap();
+}
+
+/// Finds the offsets with the sensor lying flat and still, and stores them for the next boots
+async fn calibrate(sensor: &mut Mpu6050<I2c<'static, Async>>, store: &mut CalibrationStore) {
+    let calibration_params = CalibrationParameters::new(
+        mpu6050_dmp::accel::AccelFullScale::G2,
+        mpu6050_dmp::gyro::GyroFullScale::Deg2000,
+        mpu6050_dmp::calibration::ReferenceGravity::ZN,
+    );
+
+    info!("Calibrating Sensor, keep it flat and still");
+    let (accel, gyro) = sensor
+        .calibrate(&mut Delay, &calibration_params)
+        .await
+        .unwrap();
+    info!("Sensor Calibrated");
+
+    match store.save(&accel, &gyro).await {
+        Ok(()) => info!("Calibration stored"),
+        Err(error) => warn!(
+            "Couldn't store the calibration, calibrating again next boot: {}",
+            error
+        ),
+    }
+}
+
+#[embassy_executor::task]
+async fn log_task() {
+    let mut orientation = ORIENTATION.receiver().unwrap();
+    loop {
+        let quaternion = orientation.changed().await;
+        let euler = quaternion.to_euler();
+        let gravity = quaternion.gravity();
+        info!("Orientation:");
+        info!(
+            "  Roll {}°, pitch {}°, yaw {}°",
+            euler.roll as i32, euler.pitch as i32, euler.yaw as i32
+        );
+        info!(
+            "  Gravity: x={}, y={}, z={}",
+            gravity.x, gravity.y, gravity.z
+        );
+        Timer::after_millis(1000).await;
+    }
+}
+
+#[embassy_executor::task]
+async fn link_task(mut usb_tx: UsbSerialJtagTx<'static, Async>) {
+    let heartbeat_interval = Duration::from_millis(HEARTBEAT_INTERVAL_MS);
+
+    loop {
+        // Heartbeats tell the host we are there while the DMP is quiet
+        let message = with_timeout(heartbeat_interval, OUTBOX.receive())
+            .await
+            .unwrap_or(Message::Heartbeat);
+        write(&mut usb_tx, &message).await;
+
+        if let Some((new, report)) = OUTBOX.take_drop_report() {
+            warn!("dropped {} messages for the host", new);
+            write(&mut usb_tx, &report).await;
+        }
+    }
+}
+
+async fn write(usb_tx: &mut UsbSerialJtagTx<'static, Async>, message: &Message) {
+    let mut buffer = [0u8; 32];
+    match postcard::to_slice_cobs(message, &mut buffer) {
+        Ok(frame) => {
+            _ = usb_tx.write_all(frame).await;
+            _ = usb_tx.flush().await;
+        }
+        Err(_) => error!("Couldn't serialize message for the host"),
+    }
+}
+
+#[embassy_executor::task]
+async fn host_rx_task(mut usb_rx: UsbSerialJtagRx<'static, Async>) {
+    let mut buffer = [0u8; 32];
+    let mut reader = MessageReader::<32>::new();
+
+    loop {
+        let Ok(count) = usb_rx.read(&mut buffer).await;
+
+        reader.feed(&buffer[..count], |message| {
+            // Heartbeats of the host are fine to ignore, nothing here depends on it
+            if let Ok(Message::Recalibrate) = message {
+                info!("Recalibration requested by the host");
+                RECALIBRATE.signal(());
+            }
+        });
+    }
+}
+
+#[embassy_executor::task]
+async fn button_task(mut button: Input<'static>) {
+    let mut debounced = Button::new(DEBOUNCE_MS, GestureConfig::default());
+
+    loop {
+        // Sleep until the pin changes or the debouncer or a gesture needs the time to move on
+        let deadline = debounced
+            .next_deadline()
+            .map(Instant::from_millis)
+            .unwrap_or(Instant::MAX);
+        let events = match with_deadline(deadline, button.wait_for_any_edge()).await {
+            Ok(()) => debounced.update(button.is_low(), Instant::now().as_millis()),
+            Err(_) => debounced.poll(Instant::now().as_millis()),
+        };
+
+        if events.gesture == Some(Gesture::LongPress) {
+            info!("Recalibration requested by the button");
+            RECALIBRATE.signal(());
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! The MPU6050's calibration offsets, kept in the NVS partition of the flash so the sensor only
+//! has to lie still for the first boot and whenever a recalibration is asked for.
+
+use core::ops::Range;
+
+use defmt::Format;
+use embassy_embedded_hal::adapter::BlockingAsync;
+use esp_bootloader_esp_idf::partitions::{
+    self, DataPartitionSubType, PartitionType, PARTITION_TABLE_MAX_LEN,
+};
+use esp_storage::FlashStorage;
+use mpu6050_dmp::{accel::Accel, gyro::Gyro};
+use sequential_storage::{cache::NoCache, map};
+
+/// Key of the offsets in the key-value map, a new one if the stored layout ever changes
+const OFFSETS_KEY: u8 = 1;
+
+/// Accelerometer then gyroscope offsets, in the byte order of the sensor's registers
+type Offsets = [u8; 12];
+
+/// Room for one item with its header, as `sequential_storage` writes it
+const ITEM_BUFFER_SIZE: usize = 32;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The partition table is missing or unreadable
+    PartitionTable,
+    /// The partition table has no NVS partition
+    NoPartition,
+    /// Reading or writing the flash failed
+    Flash,
+}
+
+pub struct CalibrationStore {
+    flash: BlockingAsync<FlashStorage>,
+    range: Range<u32>,
+}
+
+impl CalibrationStore {
+    /// Finds the NVS partition in the partition table the bootloader uses
+    pub fn new() -> Result<Self, Error> {
+        let mut flash = FlashStorage::new();
+        let mut buffer = [0u8; PARTITION_TABLE_MAX_LEN];
+        let table = partitions::read_partition_table(&mut flash, &mut buffer)
+            .map_err(|_| Error::PartitionTable)?;
+        let nvs = table
+            .find_partition(PartitionType::Data(DataPartitionSubType::Nvs))
+            .map_err(|_| Error::PartitionTable)?
+            .ok_or(Error::NoPartition)?;
+
+        Ok(Self {
+            flash: BlockingAsync::new(flash),
+            range: nvs.offset()..nvs.offset() + nvs.len(),
+        })
+    }
+
+    /// The offsets of the last calibration, `None` if there was none yet.
+    ///
+    /// A partition that doesn't hold our map, e.g. one an ESP-IDF firmware left behind, is
+    /// erased and counts as empty.
+    pub async fn load(&mut self) -> Result<Option<(Accel, Gyro)>, Error> {
+        let mut buffer = [0u8; ITEM_BUFFER_SIZE];
+        let offsets = map::fetch_item::<u8, Offsets, _>(
+            &mut self.flash,
+            self.range.clone(),
+            &mut NoCache::new(),
+            &mut buffer,
+            &OFFSETS_KEY,
+        )
+        .await;
+
+        match offsets {
+            Ok(offsets) => Ok(offsets.map(|offsets| {
+                let (accel, gyro) = offsets.split_at(6);
+                (
+                    Accel::from_bytes(accel.try_into().unwrap()),
+                    Gyro::from_bytes(gyro.try_into().unwrap()),
+                )
+            })),
+            Err(sequential_storage::Error::Corrupted { .. }) => {
+                sequential_storage::erase_all(&mut self.flash, self.range.clone())
+                    .await
+                    .map_err(|_| Error::Flash)?;
+                Ok(None)
+            }
+            Err(_) => Err(Error::Flash),
+        }
+    }
+
+    pub async fn save(&mut self, accel: &Accel, gyro: &Gyro) -> Result<(), Error> {
+        let mut offsets: Offsets = [0; 12];
+        offsets[..6].copy_from_slice(&accel.to_bytes());
+        offsets[6..].copy_from_slice(&gyro.to_bytes());
+
+        let mut buffer = [0u8; ITEM_BUFFER_SIZE];
+        map::store_item(
+            &mut self.flash,
+            self.range.clone(),
+            &mut NoCache::new(),
+            &mut buffer,
+            &OFFSETS_KEY,
+            &offsets,
+        )
+        .await
+        .map_err(|_| Error::Flash)
+    }
+}
@@ -0,0 +1,66 @@
+//! Pin map of the supported boards. Everything that differs between the ESP32-C3 and the
+//! ESP32-S3 build lives here, the application in `src/bin/main.rs` is the same for both.
+
+#![no_std]
+
+pub mod calibration;
+
+#[cfg(not(any(feature = "esp32c3", feature = "esp32s3")))]
+compile_error!("select the chip with the `esp32c3` or the `esp32s3` feature");
+#[cfg(all(feature = "esp32c3", feature = "esp32s3"))]
+compile_error!("the `esp32c3` and `esp32s3` features exclude each other");
+
+use esp_hal::{
+    gpio::AnyPin,
+    peripherals::{Peripherals, I2C0, SYSTIMER, USB_DEVICE},
+    time::Rate,
+};
+
+/// I2C clock of the MPU6050
+#[cfg(feature = "esp32c3")]
+pub const I2C_FREQUENCY: Rate = Rate::from_khz(100);
+#[cfg(feature = "esp32s3")]
+pub const I2C_FREQUENCY: Rate = Rate::from_khz(400);
+
+/// The peripherals the application uses, with the pins wired as on the board
+pub struct Board {
+    pub i2c: I2C0<'static>,
+    pub sda: AnyPin<'static>,
+    pub scl: AnyPin<'static>,
+    /// The MPU6050's INT pin, pulsed for every packet the DMP writes into the FIFO
+    pub int: AnyPin<'static>,
+    /// The BOOT button, held down to recalibrate the sensor
+    pub button: AnyPin<'static>,
+    pub systimer: SYSTIMER<'static>,
+    /// USB serial to the host, see the buddy-system
+    pub usb: USB_DEVICE<'static>,
+}
+
+impl Board {
+    pub fn new(peripherals: Peripherals) -> Self {
+        #[cfg(feature = "esp32c3")]
+        let (sda, scl, int, button) = (
+            peripherals.GPIO6.into(),
+            peripherals.GPIO7.into(),
+            peripherals.GPIO5.into(),
+            peripherals.GPIO9.into(),
+        );
+        #[cfg(feature = "esp32s3")]
+        let (sda, scl, int, button) = (
+            peripherals.GPIO5.into(),
+            peripherals.GPIO6.into(),
+            peripherals.GPIO4.into(),
+            peripherals.GPIO0.into(),
+        );
+
+        Self {
+            i2c: peripherals.I2C0,
+            sda,
+            scl,
+            int,
+            button,
+            systimer: peripherals.SYSTIMER,
+            usb: peripherals.USB_DEVICE,
+        }
+    }
+}
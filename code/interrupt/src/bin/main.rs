@@ -6,14 +6,16 @@
     holding buffers for the duration of a data transfer."
 )]
 
+use button::{Button, Edge, Events, GestureConfig};
 use core::cell::RefCell;
 use critical_section::Mutex;
 use defmt::info;
 use esp_hal::{
     clock::CpuClock,
     delay::Delay,
-    gpio::{Event, Input, InputConfig, Io, Level, Output, OutputConfig},
+    gpio::{Event, Input, InputConfig, Io},
     handler, main,
+    time::Instant,
 };
 
 #[panic_handler]
@@ -28,6 +30,10 @@ esp_bootloader_esp_idf::esp_app_desc!();
 // Interrupt flag
 static BUTTON: Mutex<RefCell<Option<Input>>> = Mutex::new(RefCell::new(None));
 
+// Debouncer and gesture state, fed from the interrupt and the main loop
+static DEBOUNCED: Mutex<RefCell<Button>> =
+    Mutex::new(RefCell::new(Button::new(20, GestureConfig::new())));
+
 #[main]
 fn main() -> ! {
     // generator version: 0.5.0
@@ -46,24 +52,47 @@ fn main() -> ! {
     let mut button = Input::new(peripherals.GPIO9, InputConfig::default());
 
     critical_section::with(|cs| {
-        button.listen(Event::FallingEdge);
+        // Both edges, the debouncer needs to see the release as well
+        button.listen(Event::AnyEdge);
         BUTTON.borrow_ref_mut(cs).replace(button)
     });
 
     let delay = Delay::new();
     loop {
-        delay.delay_millis(500u32);
+        // Edges only tell us that something changed. Whether the level settled and
+        // whether a button is held long enough is a matter of time, so check regularly.
+        delay.delay_millis(10u32);
+        let events = critical_section::with(|cs| DEBOUNCED.borrow_ref_mut(cs).poll(now_ms()));
+        log_events(events);
     }
 }
 
 #[handler]
 fn my_interrupt() {
     critical_section::with(|cs| {
-        info!("Button Pressed");
-        BUTTON
+        let mut button = BUTTON.borrow_ref_mut(cs);
+        let button = button.as_mut().unwrap();
+
+        let events = DEBOUNCED
             .borrow_ref_mut(cs)
-            .as_mut()
-            .unwrap()
-            .clear_interrupt();
+            .update(button.is_low(), now_ms());
+        log_events(events);
+
+        button.clear_interrupt();
     });
 }
+
+fn now_ms() -> u64 {
+    Instant::now().duration_since_epoch().as_millis()
+}
+
+fn log_events(events: Events) {
+    match events.edge {
+        Some(Edge::Pressed) => info!("Button Pressed"),
+        Some(Edge::Released) => info!("Button Released"),
+        None => {}
+    }
+    if let Some(gesture) = events.gesture {
+        info!("Gesture: {}", gesture);
+    }
+}
@@ -6,6 +6,7 @@
     holding buffers for the duration of a data transfer."
 )]
 
+use button::{Button, Edge, GestureConfig};
 use defmt::info;
 use esp_hal::clock::CpuClock;
 use esp_hal::gpio::{Input, InputConfig, Pull};
@@ -33,16 +34,22 @@ fn main() -> ! {
     let config = InputConfig::default().with_pull(Pull::Up);
     let button = Input::new(peripherals.GPIO9, config);
 
+    let mut debounced = Button::new(20, GestureConfig::default());
+
     loop {
-        let is_pressed = button.is_low();
+        let now = Instant::now().duration_since_epoch().as_millis();
+        let events = debounced.update(button.is_low(), now);
 
-        if is_pressed {
-            info!("Button pressed!");
-        } else {
-            info!("Button released!");
+        match events.edge {
+            Some(Edge::Pressed) => info!("Button pressed!"),
+            Some(Edge::Released) => info!("Button released!"),
+            None => {}
+        }
+        if let Some(gesture) = events.gesture {
+            info!("Gesture: {}", gesture);
         }
 
         let delay_start = Instant::now();
-        while delay_start.elapsed() < Duration::from_millis(500) {}
+        while delay_start.elapsed() < Duration::from_millis(10) {}
     }
 }
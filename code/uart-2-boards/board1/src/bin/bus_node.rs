@@ -0,0 +1,123 @@
+#![no_std]
+#![no_main]
+
+// Sensor node on a multi-drop bus, answering board2's bus master when polled.
+//
+// Every node on the bus needs its own address, set when building:
+//   NODE_ADDRESS=2 cargo run --release --bin bus-node
+//
+// RS-485 transceiver: DI on GPIO4, RO on GPIO5, DE and /RE together on GPIO3.
+
+use embassy_time::{with_deadline, Duration, Instant};
+use esp_hal::{
+    gpio::{Level, Output, OutputConfig},
+    timer::timg::TimerGroup,
+    uart::{Uart, Config, UartRx, UartTx},
+    Async,
+};
+use esp_println::println;
+use esp32c3_sensor_board::{receive_frame, MockSensor};
+use protocol::{
+    bus::{parse_address, BusConfig, Node, NodeEvent},
+    DataFrame, Frame, FrameParser, SENSORS,
+};
+use esp_backtrace as _;
+use embedded_io_async::Write;
+
+const NODE_ADDRESS: u8 = match option_env!("NODE_ADDRESS") {
+    Some(address) => parse_address(address),
+    None => 1,
+};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[esp_hal_embassy::main]
+async fn main(spawner: embassy_executor::Spawner) {
+    let peripherals = esp_hal::init(esp_hal::Config::default());
+
+    println!("ESP32-C3 Bus Node {} Starting...", NODE_ADDRESS);
+
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    esp_hal_embassy::init(timg0.timer0);
+
+    // Driver enable, low means listening
+    let driver_enable = Output::new(peripherals.GPIO3, Level::Low, OutputConfig::default());
+
+    let config = Config::default().with_baudrate(115200);
+    let uart = Uart::new(peripherals.UART1, config).unwrap()
+        .with_tx(peripherals.GPIO4)
+        .with_rx(peripherals.GPIO5)
+        .into_async();
+
+    println!("Bus on GPIO4(TX)/GPIO5(RX), driver enable on GPIO3, at 115200 baud");
+
+    let (rx, tx) = uart.split();
+
+    spawner.must_spawn(node_task(rx, tx, driver_enable));
+}
+
+#[embassy_executor::task]
+async fn node_task(
+    mut uart_rx: UartRx<'static, Async>,
+    mut uart_tx: UartTx<'static, Async>,
+    mut driver_enable: Output<'static>
+) {
+    // Nodes with the same seed would all report the same values
+    let mut sensor = MockSensor::new(12345 + NODE_ADDRESS as u64);
+    let mut node = Node::new(NODE_ADDRESS, BusConfig::default());
+    let mut encoder = DataFrame::new();
+    encoder.set_address(NODE_ADDRESS);
+    let mut parser = FrameParser::new();
+    let mut next_sample = Instant::now();
+
+    loop {
+        let now = Instant::now();
+        if now >= next_sample {
+            for info in &SENSORS {
+                let Some(reading) = sensor.read(info) else {
+                    continue;
+                };
+                if let Some(dropped) = node.push(reading) {
+                    println!("Not polled in time, dropped reading {}", dropped.timestamp);
+                }
+            }
+            next_sample += SAMPLE_INTERVAL;
+        }
+
+        if let Some(NodeEvent::Transmit(frame)) = node.poll(now.as_millis()) {
+            transmit(&mut uart_tx, &mut driver_enable, &mut encoder, &frame).await;
+            continue;
+        }
+
+        let deadline = node
+            .next_deadline()
+            .map_or(next_sample, |deadline| Instant::from_millis(deadline).min(next_sample));
+        // Nothing arriving in time is fine, there is something else to do then
+        let Ok(frame) = with_deadline(deadline, receive_frame(&mut uart_rx, &mut parser)).await else {
+            continue;
+        };
+        let event = node.on_frame(parser.address(), &frame, Instant::now().as_millis());
+        if let Some(NodeEvent::Delivered { seq }) = event {
+            println!("Frame {} delivered", seq);
+        }
+    }
+}
+
+async fn transmit(
+    uart_tx: &mut UartTx<'static, Async>,
+    driver_enable: &mut Output<'static>,
+    encoder: &mut DataFrame,
+    frame: &Frame
+) {
+    if encoder.build_frame(frame).is_err() {
+        return;
+    }
+
+    // Drive the bus only as long as the frame takes, the last byte included
+    driver_enable.set_high();
+    if let Err(e) = uart_tx.write_all(encoder.get_bytes()).await {
+        println!("UART write error: {:?}", e);
+    }
+    _ = uart_tx.flush_async().await;
+    driver_enable.set_low();
+}
@@ -1,120 +1,49 @@
 #![no_std]
 #![no_main]
 
-use embassy_time::{Duration, Timer};
+use embassy_time::{with_deadline, Duration, Instant, TimeoutError, Timer};
 use esp_hal::{
     gpio::{Level, Output, OutputConfig},
     timer::timg::TimerGroup,
-    uart::{Uart, Config, UartTx},
+    uart::{Uart, Config},
     Async,
 };
 use esp_println::println;
-use heapless::Vec;
-use rand::{RngCore, SeedableRng};
-use rand::rngs::SmallRng;
+use esp32c3_sensor_board::{receive_frame, MockSensor};
+use protocol::{
+    baud::{BaudConfig, BaudEvent, Negotiator, SAFE_BAUD_RATE},
+    schedule::{uart_bytes_per_second, RateLimiter, ReadingQueue, SensorSchedule, Scheduler},
+    sensor_info, DataFrame, Frame, FrameParser, RetransmitConfig, Sender, SenderEvent,
+    MAX_FRAME_SIZE, MAX_READINGS_PER_FRAME, SENSORS, SENSOR_HUMIDITY, SENSOR_LIGHT,
+    SENSOR_PRESSURE, SENSOR_TEMPERATURE, SENSOR_VOLTAGE,
+};
+#[cfg(feature = "auth")]
+use protocol::Key;
 use esp_backtrace as _;
 use embedded_io_async::Write;
 
-// Protocol constants
-const START_BYTE: u8 = 0xAA;
-const END_BYTE: u8 = 0x55;
-const SENSOR_HUMIDITY: u8 = 0x02;
-
-// Sensor data structure
-#[derive(Clone, Copy)]
-struct SensorReading {
-    sensor_id: u8,
-    value: f32,
-    timestamp: u32,
-}
-
-// Protocol frame structure
-struct DataFrame {
-    data: Vec<u8, 32>,
-}
-
-impl DataFrame {
-    fn new() -> Self {
-        Self {
-            data: Vec::new(),
-        }
-    }
+// Shared with board2, see the `auth` feature
+#[cfg(feature = "auth")]
+const LINK_KEY: Key = Key::from_hex(env!("LINK_KEY", "the auth feature needs LINK_KEY=<32 hex digits>"));
 
-    fn build_frame(&mut self, reading: &SensorReading) -> Result<(), ()> {
-        self.data.clear();
-        
-        // Convert f32 to bytes
-        let value_bytes = reading.value.to_le_bytes();
-        let timestamp_bytes = reading.timestamp.to_le_bytes();
-        
-        // Calculate data length (1 + 4 + 4 = 9 bytes)
-        let data_length = 9u8;
-        
-        // Build frame
-        self.data.push(START_BYTE).map_err(|_| ())?;
-        self.data.push(reading.sensor_id).map_err(|_| ())?;
-        self.data.push(data_length).map_err(|_| ())?;
-        
-        // Add sensor ID again in data section
-        self.data.push(reading.sensor_id).map_err(|_| ())?;
-        
-        // Add value bytes
-        for byte in value_bytes.iter() {
-            self.data.push(*byte).map_err(|_| ())?;
-        }
-        
-        // Add timestamp bytes
-        for byte in timestamp_bytes.iter() {
-            self.data.push(*byte).map_err(|_| ())?;
-        }
-        
-        // Calculate XOR checksum
-        let mut checksum = 0u8;
-        for i in 1..self.data.len() {
-            checksum ^= self.data[i];
-        }
-        
-        self.data.push(checksum).map_err(|_| ())?;
-        self.data.push(END_BYTE).map_err(|_| ())?;
-        
-        Ok(())
-    }
-    
-    fn get_bytes(&self) -> &[u8] {
-        &self.data
-    }
-}
+// How often each fake sensor is sampled, fast and slow ones share the link.
+// The phases keep them from all coming at once.
+const SCHEDULE: [SensorSchedule; SENSORS.len()] = [
+    SensorSchedule::every(SENSOR_LIGHT, 100),
+    SensorSchedule::every(SENSOR_TEMPERATURE, 1000).with_phase(20),
+    SensorSchedule::every(SENSOR_PRESSURE, 2000).with_phase(40),
+    SensorSchedule::every(SENSOR_HUMIDITY, 5000).with_phase(60),
+    SensorSchedule::every(SENSOR_VOLTAGE, 10000).with_phase(80),
+];
 
-// Mock sensor that generates realistic readings
-struct MockSensor {
-    rng: SmallRng,
-    base_humidity: f32,
-    timestamp: u32,
-}
+// Readings waiting for board2, the oldest are dropped when it can't keep up
+const QUEUE_LENGTH: usize = 4 * MAX_READINGS_PER_FRAME;
 
-impl MockSensor {
-    fn new() -> Self {
-        Self {
-            rng: SmallRng::seed_from_u64(12345), // Fixed seed for reproducible testing
-            base_humidity: 45.0,  // Base humidity in %
-            timestamp: 0,
-        }
-    }
-    
-    fn read_humidity(&mut self) -> SensorReading {
-        self.timestamp += 1;
-        // Generate humidity between 30-70% with some noise
-        let noise = (self.rng.next_u32() % 1000) as f32 / 1000.0 - 0.5;
-        let value = self.base_humidity + noise * 20.0; // ±10% variation
-        let value = value.max(0.0).min(100.0); // Clamp to valid range
-        
-        SensorReading {
-            sensor_id: SENSOR_HUMIDITY,
-            value,
-            timestamp: self.timestamp,
-        }
-    }
+// Half of the line for our frames, board2's answers and retransmissions need room too
+const fn link_budget(baud_rate: u32) -> u32 {
+    uart_bytes_per_second(baud_rate) / 2
 }
+const BURST_BYTES: u32 = MAX_FRAME_SIZE as u32;
 
 #[esp_hal_embassy::main]
 async fn main(spawner: embassy_executor::Spawner) {
@@ -130,59 +59,170 @@ async fn main(spawner: embassy_executor::Spawner) {
 
     // Configure UART for communication with custom pins
     // TX: GPIO4, RX: GPIO5
-    let config = Config::default().with_baudrate(115200);
+    // Starts slow, board2 and we then agree on the fastest rate the wires carry
+    let config = Config::default().with_baudrate(SAFE_BAUD_RATE);
     
     let uart = Uart::new(peripherals.UART1, config).unwrap()
         .with_tx(peripherals.GPIO4)
         .with_rx(peripherals.GPIO5)
         .into_async();
     
-    println!("UART configured on GPIO4(TX)/GPIO5(RX) at 115200 baud");
-    
-    let (_rx, tx) = uart.split();
+    println!("UART configured on GPIO4(TX)/GPIO5(RX) at {} baud", SAFE_BAUD_RATE);
     
-    spawner.must_spawn(sensor_main_task(tx, led));
+    // Not split, only the whole UART can change its baud rate
+    spawner.must_spawn(sensor_main_task(uart, led));
 }
 
 #[embassy_executor::task]
-async fn sensor_main_task(
-    uart_tx: UartTx<'static, Async>,
-    led: Output<'static>
-) {
-    sensor_task(uart_tx, led).await;
+async fn sensor_main_task(uart: Uart<'static, Async>, led: Output<'static>) {
+    sensor_task(uart, led).await;
 }
 
-async fn sensor_task(
-    mut uart_tx: UartTx<'static, Async>,
-    mut led: Output<'static>
-) {
-    let mut sensor = MockSensor::new();
-    let mut frame = DataFrame::new();
+async fn sensor_task(mut uart: Uart<'static, Async>, mut led: Output<'static>) {
+    let mut sensor = MockSensor::new(12345);
+    #[cfg(feature = "auth")]
+    let (mut frame, mut parser) = (DataFrame::authenticated(LINK_KEY), FrameParser::authenticated(LINK_KEY));
+    #[cfg(not(feature = "auth"))]
+    let (mut frame, mut parser) = (DataFrame::new(), FrameParser::new());
+    let mut sender = Sender::new(RetransmitConfig::for_baud_rate(SAFE_BAUD_RATE));
+    let mut scheduler = Scheduler::new(SCHEDULE, Instant::now().as_millis());
+    let mut queue: ReadingQueue<QUEUE_LENGTH> = ReadingQueue::new();
+    let mut limiter = RateLimiter::new(link_budget(SAFE_BAUD_RATE), BURST_BYTES, Instant::now().as_millis());
+    let mut negotiator = Negotiator::new(BaudConfig::default(), Instant::now().as_millis());
+    // Answer of board2 to the negotiation, handled like the negotiator's own events
+    let mut baud_event = None;
     
     println!("Starting sensor reading task...");
     
     loop {
-        let humidity_reading = sensor.read_humidity();
-        if frame.build_frame(&humidity_reading).is_ok() {
-            send_frame(&mut uart_tx, &frame, &mut led).await;
-            println!("Sent fake humidity: {:.1}%", humidity_reading.value);
+        let now = Instant::now().as_millis();
+        while let Some(sensor_id) = scheduler.next_due(now) {
+            let Some(info) = sensor_info(sensor_id) else {
+                continue;
+            };
+            let Some(reading) = sensor.read(info) else {
+                println!("Fake {} dropped out", info.name);
+                continue;
+            };
+            println!(
+                "Read fake {}: {:.*} {}",
+                info.name, info.precision as usize, reading.value, info.unit
+            );
+            if let Some(dropped) = queue.push(reading) {
+                println!("Queue full, dropped reading {}", dropped.timestamp);
+            }
         }
         
-        Timer::after(Duration::from_millis(1000)).await;
+        // The baud rate comes first, data frames wait until it's settled
+        match baud_event.take().or_else(|| negotiator.poll(now)) {
+            Some(BaudEvent::Transmit(baud)) => {
+                send_frame(&mut uart, &mut frame, &baud, &mut led).await;
+                continue;
+            }
+            Some(BaudEvent::SwitchTo(rate)) => {
+                // Whatever was written still goes out at the old rate
+                _ = uart.flush_async().await;
+                if let Err(e) = uart.apply_config(&Config::default().with_baudrate(rate)) {
+                    println!("UART config error: {:?}", e);
+                }
+                parser.reset();
+                sender.set_config(RetransmitConfig::for_baud_rate(rate));
+                limiter = RateLimiter::new(link_budget(rate), BURST_BYTES, Instant::now().as_millis());
+                println!("Switched to {} baud", rate);
+                continue;
+            }
+            Some(BaudEvent::Settled(rate)) => {
+                println!("Link settled at {} baud", rate);
+                continue;
+            }
+            None => {}
+        }
+        let settled = negotiator.is_settled();
+        
+        // One frame in flight at a time, with as many readings as board2 accepts, and only as
+        // fast as the link allows
+        let ready = settled && sender.is_idle() && !queue.is_empty();
+        if ready && limiter.is_ready(now) {
+            let readings = queue.take(sender.batch_size());
+            // Can't fail, the sender is idle and the batch fits
+            if let Ok(data) = sender.send(&readings, now) {
+                send_frame(&mut uart, &mut frame, &data, &mut led).await;
+                limiter.record(frame.get_bytes().len(), Instant::now().as_millis());
+                negotiator.on_sent(Instant::now().as_millis());
+                println!("Sent frame {} with {} readings", data.seq(), readings.len());
+            }
+            continue;
+        }
+        
+        // Wait for the next sensor, board2's answer, the negotiation or the link budget,
+        // whatever comes first
+        let mut deadline = scheduler.next_deadline().unwrap_or(u64::MAX);
+        if let Some(ack_deadline) = sender.next_deadline().filter(|_| settled) {
+            deadline = deadline.min(ack_deadline);
+        }
+        if let Some(baud_deadline) = negotiator.next_deadline() {
+            deadline = deadline.min(baud_deadline);
+        }
+        if ready {
+            deadline = deadline.min(limiter.ready_at());
+        }
+        let event = match with_deadline(
+            Instant::from_millis(deadline),
+            receive_frame(&mut uart, &mut parser),
+        )
+        .await
+        {
+            Ok(answer) => {
+                // Count on from board2, after a restart that's how we catch up
+                frame.sync_counter(parser.last_counter());
+                let now = Instant::now().as_millis();
+                baud_event = negotiator.on_frame(&answer, now);
+                sender.on_frame(&answer, now)
+            }
+            Err(TimeoutError) if settled => sender.poll(Instant::now().as_millis()),
+            Err(TimeoutError) => None,
+        };
+        
+        // Retransmissions are mostly frames board2 got broken, too many step the rate down
+        match event {
+            Some(SenderEvent::Transmit(data)) => {
+                negotiator.on_failure(Instant::now().as_millis());
+                println!("Retransmitting frame {}", data.seq());
+                send_frame(&mut uart, &mut frame, &data, &mut led).await;
+                limiter.record(frame.get_bytes().len(), Instant::now().as_millis());
+                negotiator.on_sent(Instant::now().as_millis());
+            }
+            Some(SenderEvent::Delivered { seq, attempts }) => {
+                negotiator.on_delivered();
+                if attempts > 1 {
+                    println!("Frame {} delivered after {} attempts", seq, attempts);
+                }
+            }
+            Some(SenderEvent::GaveUp { seq }) => {
+                negotiator.on_failure(Instant::now().as_millis());
+                println!("Frame {} not acknowledged, giving up", seq);
+            }
+            None => {}
+        }
     }
 }
 
 async fn send_frame(
-    uart_tx: &mut UartTx<'static, Async>,
-    frame: &DataFrame,
+    uart: &mut Uart<'static, Async>,
+    encoder: &mut DataFrame,
+    frame: &Frame,
     indicator_led: &mut Output<'static>
 ) {
+    if encoder.build_frame(frame).is_err() {
+        return;
+    }
+    
     indicator_led.set_high();
     
-    let bytes = frame.get_bytes();
-    uart_tx.write_all(bytes).await.unwrap();
+    let bytes = encoder.get_bytes();
+    uart.write_all(bytes).await.unwrap();
 
     Timer::after(Duration::from_millis(20)).await;
 
     indicator_led.set_low();
-}
\ No newline at end of file
+}
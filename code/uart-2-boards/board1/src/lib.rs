@@ -1 +1,84 @@
 #![no_std]
+
+// Fake sensors and frame reception, shared by the point-to-point and the bus firmware
+
+use embedded_io_async::Read;
+use esp_println::println;
+use protocol::{
+    Frame, FrameParser, SensorInfo, SensorReading, SENSORS, SENSOR_HUMIDITY, SENSOR_LIGHT,
+    SENSOR_PRESSURE, SENSOR_TEMPERATURE, SENSOR_VOLTAGE,
+};
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
+use waveform::{Model, Shape, Signal};
+
+// Signal of each fake sensor, periods are in samples, see the schedule in main.rs.
+// Add `.with_fault(Fault::...)` to see how board2 copes with spikes, stuck sensors or dropouts.
+const MODELS: [(u8, Model); SENSORS.len()] = [
+    (
+        SENSOR_TEMPERATURE,
+        Model::noisy(22.0, 0.3).with_shape(Shape::Sine { amplitude: 3.0, period: 120 }),
+    ),
+    (SENSOR_HUMIDITY, Model::noisy(45.0, 10.0)),
+    (
+        SENSOR_PRESSURE,
+        Model::noisy(1013.0, 0.2).with_shape(Shape::RandomWalk { step: 1.0, limit: 15.0 }),
+    ),
+    (
+        SENSOR_LIGHT,
+        Model::noisy(100.0, 20.0).with_shape(Shape::Steps { height: 400.0, every: 30 }),
+    ),
+    (SENSOR_VOLTAGE, Model::noisy(3.3, 0.1)),
+];
+
+// Mock sensor that generates readings following the models
+pub struct MockSensor {
+    rng: SmallRng,
+    signals: [(u8, Signal); MODELS.len()],
+    timestamp: u32,
+}
+
+impl MockSensor {
+    // Fixed seed for reproducible testing, different boards should use different ones
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: SmallRng::seed_from_u64(seed),
+            signals: MODELS.map(|(id, model)| (id, Signal::new(model))),
+            timestamp: 0,
+        }
+    }
+    
+    // None when the model let the reading drop out
+    pub fn read(&mut self, sensor: &SensorInfo) -> Option<SensorReading> {
+        // Counts dropped readings too, board2 sees them as lost
+        self.timestamp += 1;
+        let signal = self.signals.iter_mut().find(|(id, _)| *id == sensor.id);
+        // Faults may well go out of range, that's for board2 to notice
+        let value = match signal {
+            Some((_, signal)) => signal.next(&mut self.rng)?,
+            None => sensor.min,
+        };
+        
+        Some(SensorReading {
+            sensor_id: sensor.id,
+            value,
+            timestamp: self.timestamp,
+        })
+    }
+}
+
+// Read from the UART until a complete frame arrived
+pub async fn receive_frame(uart: &mut impl Read, parser: &mut FrameParser) -> Frame {
+    let mut buffer = [0u8; 1];
+    
+    loop {
+        match uart.read(&mut buffer).await {
+            Ok(_) => match parser.process_byte(buffer[0]) {
+                Ok(Some(frame)) => return frame,
+                Ok(None) => {}
+                Err(e) => println!("{}", e),
+            },
+            Err(e) => println!("UART read error: {:?}", e),
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,173 @@
+#![no_std]
+#![no_main]
+
+// Bus master polling the sensor nodes on a multi-drop bus, see board1's bus-node.
+//
+// RS-485 transceiver: DI on GPIO4, RO on GPIO5, DE and /RE together on GPIO3.
+
+use embassy_time::{with_deadline, Duration, Instant, TimeoutError};
+use esp_hal::{
+    gpio::{Level, Output, OutputConfig},
+    timer::timg::TimerGroup,
+    uart::{Uart, Config, UartRx, UartTx},
+    Async,
+};
+use esp_println::{print, println};
+use esp32c3_display_board::display_sensor_reading;
+use protocol::{
+    baud::inter_byte_timeout_ms,
+    bus::{BusConfig, Master, MasterEvent},
+    DataFrame, Frame, FrameParser, ParseState, Statistics, MAX_READINGS_PER_FRAME,
+};
+use esp_backtrace as _;
+use embedded_io_async::Write;
+
+// Addresses of the nodes to poll
+const NODES: [u8; 3] = [1, 2, 3];
+
+// The bus always runs at this rate, the nodes use the same
+const BAUD_RATE: u32 = 115_200;
+
+// Silent nodes are reported after this many polls in a row, and then every so often
+const MISSED_POLLS_REPORTED: u8 = 5;
+
+// How often the statistics are printed
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(10);
+
+#[esp_hal_embassy::main]
+async fn main(spawner: embassy_executor::Spawner) {
+    let peripherals = esp_hal::init(esp_hal::Config::default());
+
+    println!("ESP32-C3 Bus Master Starting...");
+
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    esp_hal_embassy::init(timg0.timer0);
+
+    // Driver enable, low means listening
+    let driver_enable = Output::new(peripherals.GPIO3, Level::Low, OutputConfig::default());
+
+    let config = Config::default().with_baudrate(BAUD_RATE);
+    let uart = Uart::new(peripherals.UART1, config).unwrap()
+        .with_rx(peripherals.GPIO5)
+        .with_tx(peripherals.GPIO4)
+        .into_async();
+
+    println!("Bus on GPIO4(TX)/GPIO5(RX), driver enable on GPIO3, at {} baud", BAUD_RATE);
+    println!("Polling nodes {:?}\n", NODES);
+
+    let (rx, tx) = uart.split();
+
+    spawner.must_spawn(master_task(rx, tx, driver_enable));
+}
+
+#[embassy_executor::task]
+async fn master_task(
+    mut uart_rx: UartRx<'static, Async>,
+    mut uart_tx: UartTx<'static, Async>,
+    mut driver_enable: Output<'static>
+) {
+    let mut master = Master::new(MAX_READINGS_PER_FRAME as u8, BusConfig::default());
+    for address in NODES {
+        master.add_node(address).unwrap();
+    }
+    let mut encoder = DataFrame::new();
+    let mut parser = FrameParser::new();
+    // Per node, the timestamps of different nodes have nothing to do with each other
+    let mut stats = NODES.map(|_| Statistics::new(Instant::now().as_millis()));
+    let mut next_summary = Instant::now() + SUMMARY_INTERVAL;
+
+    let mut buffer = [0u8; 32];
+    let inter_byte_timeout = Duration::from_millis(inter_byte_timeout_ms(BAUD_RATE, buffer.len()));
+
+    loop {
+        match master.poll(Instant::now().as_millis()) {
+            Some(MasterEvent::Transmit { address, frame }) => {
+                transmit(&mut uart_tx, &mut driver_enable, &mut encoder, address, &frame).await;
+                continue;
+            }
+            Some(MasterEvent::NoResponse { address, missed }) => {
+                if missed % MISSED_POLLS_REPORTED == 0 {
+                    println!("Node {} missed {} polls in a row", address, missed);
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        if Instant::now() >= next_summary {
+            for (address, stats) in NODES.iter().zip(&mut stats) {
+                println!("\nNode {}:\n{}\n", address, stats.summary(Instant::now().as_millis()));
+            }
+            next_summary = Instant::now() + SUMMARY_INTERVAL;
+        }
+
+        // Wait as long as the master lets us for a frame to start, but not for the rest of a
+        // started one
+        let frame_deadline = (parser.state() != &ParseState::WaitingForStart)
+            .then(|| Instant::now() + inter_byte_timeout);
+        let mut deadline = Instant::from_millis(master.next_deadline()).min(next_summary);
+        if let Some(frame_deadline) = frame_deadline {
+            deadline = deadline.min(frame_deadline);
+        }
+        let count = match with_deadline(deadline, uart_rx.read_async(&mut buffer)).await {
+            Ok(Ok(count)) => count,
+            Ok(Err(e)) => {
+                println!("UART read error: {:?}", e);
+                continue;
+            }
+            Err(TimeoutError) => {
+                // The poll or summary deadline may have come first, in the middle of a frame
+                if frame_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    if let Some(e) = parser.on_timeout() {
+                        println!("{}", e);
+                    }
+                }
+                continue;
+            }
+        };
+
+        for &byte in &buffer[..count] {
+            let frame = match parser.process_byte(byte) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => continue,
+                Err(e) => {
+                    println!("{}", e);
+                    continue;
+                }
+            };
+            let address = parser.address();
+            let event = master.on_frame(address, &frame, Instant::now().as_millis());
+            let Some(MasterEvent::Readings { address, readings }) = event else {
+                continue;
+            };
+            if let Some(index) = NODES.iter().position(|node| *node == address) {
+                stats[index].record_frame(&readings);
+            }
+            for reading in &readings {
+                print!("[node {}] ", address);
+                display_sensor_reading(reading);
+            }
+        }
+    }
+}
+
+async fn transmit(
+    uart_tx: &mut UartTx<'static, Async>,
+    driver_enable: &mut Output<'static>,
+    encoder: &mut DataFrame,
+    address: u8,
+    frame: &Frame
+) {
+    encoder.set_address(address);
+    if encoder.build_frame(frame).is_err() {
+        return;
+    }
+
+    // Drive the bus only as long as the frame takes, the last byte included
+    driver_enable.set_high();
+    if let Err(e) = uart_tx.write_all(encoder.get_bytes()).await {
+        println!("UART write error: {:?}", e);
+    }
+    _ = uart_tx.flush_async().await;
+    driver_enable.set_low();
+}
@@ -1,214 +1,49 @@
 #![no_std]
 #![no_main]
 
-use embassy_time::{Duration, Timer};
+use common::{link::HEARTBEAT_INTERVAL_MS, Message};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, signal::Signal};
+use embassy_time::{with_deadline, with_timeout, Duration, Instant, TimeoutError, Timer};
 use esp_hal::{
     gpio::{Level, Output, OutputConfig},
     timer::timg::TimerGroup,
-    uart::{Uart, Config, UartRx},
+    uart::{Uart, Config},
+    usb_serial_jtag::{UsbSerialJtag, UsbSerialJtagTx},
     Async,
 };
 use esp_println::println;
-use heapless::Vec;
+use esp32c3_display_board::display_sensor_reading;
+use protocol::{
+    baud::{inter_byte_timeout_ms, BaudConfig, BaudEvent, Responder, SAFE_BAUD_RATE},
+    DataFrame, Frame, FrameParser, ParseState, Receiver, SensorReading, Statistics,
+    MAX_READINGS_PER_FRAME,
+};
+#[cfg(feature = "auth")]
+use protocol::Key;
 use esp_backtrace as _;
+use embedded_io_async::Write;
 
-// Protocol constants
-const START_BYTE: u8 = 0xAA;
-const END_BYTE: u8 = 0x55;
-const SENSOR_HUMIDITY: u8 = 0x02;
+// Shared with board1, see the `auth` feature
+#[cfg(feature = "auth")]
+const LINK_KEY: Key = Key::from_hex(env!("LINK_KEY", "the auth feature needs LINK_KEY=<32 hex digits>"));
 
-// Maximum frame size
-const MAX_FRAME_SIZE: usize = 32;
+// How often the statistics are printed
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(10);
 
-// Received sensor data
-#[derive(Clone, Copy, Debug)]
-struct SensorReading {
-    sensor_id: u8,
-    value: f32,
-    timestamp: u32,
-}
+// How many messages can wait for the USB serial task
+const HOST_QUEUE_DEPTH: usize = 16;
 
-// Frame parser state machine
-#[derive(Debug, PartialEq)]
-enum ParseState {
-    WaitingForStart,
-    ReadingSensorId,
-    ReadingLength,
-    ReadingData,
-    ReadingChecksum,
-    ReadingEnd,
-}
+// How long the LED lights up for a data frame
+const BLINK_DURATION: Duration = Duration::from_millis(50);
 
-struct FrameParser {
-    state: ParseState,
-    buffer: Vec<u8, MAX_FRAME_SIZE>,
-    sensor_id: u8,
-    data_length: u8,
-    bytes_read: usize,
-    expected_checksum: u8,
-}
+// Readings on their way to the host, the UART link never waits for it
+static TO_HOST: Channel<CriticalSectionRawMutex, Message, HOST_QUEUE_DEPTH> = Channel::new();
 
-impl FrameParser {
-    fn new() -> Self {
-        Self {
-            state: ParseState::WaitingForStart,
-            buffer: Vec::new(),
-            sensor_id: 0,
-            data_length: 0,
-            bytes_read: 0,
-            expected_checksum: 0,
-        }
-    }
-    
-    fn reset(&mut self) {
-        self.state = ParseState::WaitingForStart;
-        self.buffer.clear();
-        self.sensor_id = 0;
-        self.data_length = 0;
-        self.bytes_read = 0;
-        self.expected_checksum = 0;
-    }
-    
-    fn process_byte(&mut self, byte: u8) -> Option<SensorReading> {
-        match self.state {
-            ParseState::WaitingForStart => {
-                if byte == START_BYTE {
-                    self.buffer.clear();
-                    self.state = ParseState::ReadingSensorId;
-                }
-            }
-            
-            ParseState::ReadingSensorId => {
-                self.sensor_id = byte;
-                self.expected_checksum = byte; // Start checksum calculation
-                self.state = ParseState::ReadingLength;
-            }
-            
-            ParseState::ReadingLength => {
-                self.data_length = byte;
-                self.expected_checksum ^= byte;
-                self.bytes_read = 0;
-                self.buffer.clear();
-                
-                if self.data_length > 0 && self.data_length <= 20 {
-                    self.state = ParseState::ReadingData;
-                } else {
-                    println!("Invalid data length: {}", self.data_length);
-                    self.reset();
-                }
-            }
-            
-            ParseState::ReadingData => {
-                if self.buffer.push(byte).is_err() {
-                    println!("Buffer overflow during data reading");
-                    self.reset();
-                    return None;
-                }
-                
-                self.expected_checksum ^= byte;
-                self.bytes_read += 1;
-                
-                if self.bytes_read >= self.data_length as usize {
-                    self.state = ParseState::ReadingChecksum;
-                }
-            }
-            
-            ParseState::ReadingChecksum => {
-                if byte == self.expected_checksum {
-                    self.state = ParseState::ReadingEnd;
-                } else {
-                    println!("Checksum mismatch: got {:#02X}, expected {:#02X}", 
-                            byte, self.expected_checksum);
-                    self.reset();
-                }
-            }
-            
-            ParseState::ReadingEnd => {
-                if byte == END_BYTE {
-                    // Frame complete, parse the data
-                    let reading = self.parse_sensor_data();
-                    self.reset();
-                    return reading;
-                } else {
-                    println!("Invalid end byte: {:#02X}", byte);
-                    self.reset();
-                }
-            }
-        }
-        
-        None
-    }
-    
-    fn parse_sensor_data(&self) -> Option<SensorReading> {
-        if self.buffer.len() < 9 {
-            println!("Insufficient data in buffer: {} bytes", self.buffer.len());
-            return None;
-        }
-        
-        // Data format: [sensor_id][value(4 bytes)][timestamp(4 bytes)]
-        let received_sensor_id = self.buffer[0];
-        
-        if received_sensor_id != self.sensor_id {
-            println!("Sensor ID mismatch in data");
-            return None;
-        }
-        
-        // Extract value (f32, little endian)
-        let mut value_bytes = [0u8; 4];
-        value_bytes.copy_from_slice(&self.buffer[1..5]);
-        let value = f32::from_le_bytes(value_bytes);
-        
-        // Extract timestamp (u32, little endian)
-        let mut timestamp_bytes = [0u8; 4];
-        timestamp_bytes.copy_from_slice(&self.buffer[5..9]);
-        let timestamp = u32::from_le_bytes(timestamp_bytes);
-        
-        Some(SensorReading {
-            sensor_id: received_sensor_id,
-            value,
-            timestamp,
-        })
-    }
-}
-
-// Statistics tracking
-struct Statistics {
-    humidity_count: u32,
-    total_frames: u32,
-    last_humidity: f32,
-}
-
-impl Statistics {
-    fn new() -> Self {
-        Self {
-            humidity_count: 0,
-            total_frames: 0,
-            last_humidity: 0.0,
-        }
-    }
-    
-    fn update(&mut self, reading: &SensorReading) {
-        self.total_frames += 1;
-        
-        match reading.sensor_id {
-            SENSOR_HUMIDITY => {
-                self.humidity_count += 1;
-                self.last_humidity = reading.value;
-            }
-            _ => {}
-        }
-    }
-    
-    fn print_summary(&self) {
-        println!("\n=== SENSOR STATISTICS ===");
-        println!("Total frames received: {}", self.total_frames);
-        println!("Humidity readings: {} (last: {:.1}%)", self.humidity_count, self.last_humidity);
-        println!("========================\n");
-    }
-}
+// A data frame arrived, the LED task blinks so the receive loop never waits for it
+static BLINK: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
 #[esp_hal_embassy::main]
-async fn main(_spawner: embassy_executor::Spawner) {
+async fn main(spawner: embassy_executor::Spawner) {
     println!("Welcome to board2 main");
     let peripherals = esp_hal::init(esp_hal::Config::default());
     
@@ -222,88 +57,242 @@ async fn main(_spawner: embassy_executor::Spawner) {
     
     // Configure UART for communication
     // RX: GPIO5, TX: GPIO4 (reverse of Board 1)
-    let config = Config::default().with_baudrate(115200);
+    // Starts slow, board1 then proposes faster rates
+    let config = Config::default().with_baudrate(SAFE_BAUD_RATE);
     
     let uart = Uart::new(peripherals.UART1, config).unwrap()
         .with_rx(peripherals.GPIO5)
         .with_tx(peripherals.GPIO4)
         .into_async();
     
-    println!("UART configured on GPIO5(RX)/GPIO4(TX) at 115200 baud");
+    println!("UART configured on GPIO5(RX)/GPIO4(TX) at {} baud", SAFE_BAUD_RATE);
     println!("Waiting for sensor data...\n");
     
-    // Split for reception
-    let (rx, _tx) = uart.split();
+    // Readings are forwarded to the host over USB serial, the logs stay on UART0
+    let (_, usb_tx) = UsbSerialJtag::new(peripherals.USB_DEVICE).into_async().split();
+    spawner
+        .spawn(usb_forward_task(usb_tx))
+        .expect("Could not spawn USB forward task");
+    spawner
+        .spawn(led_task(led))
+        .expect("Could not spawn LED task");
     
-    // Run the async task
-    uart_receive_task(rx, led).await;
+    // Run the async task, with the whole UART since only that can change its baud rate
+    uart_receive_task(uart).await;
 }
 
-async fn uart_receive_task(
-    mut uart_rx: UartRx<'static, Async>,
-    mut led: Output<'static>
-) {
-    let mut parser = FrameParser::new();
-    let mut stats = Statistics::new();
+async fn uart_receive_task(mut uart: Uart<'static, Async>) {
+    #[cfg(feature = "auth")]
+    let (mut parser, mut encoder) = (FrameParser::authenticated(LINK_KEY), DataFrame::authenticated(LINK_KEY));
+    // Frames recorded before a reset are still replays after it
+    #[cfg(feature = "auth")]
+    {
+        parser.resume_counter(esp32c3_display_board::counter::load());
+        encoder.sync_counter(parser.last_counter());
+    }
+    #[cfg(not(feature = "auth"))]
+    let (mut parser, mut encoder) = (FrameParser::new(), DataFrame::new());
+    let mut receiver = Receiver::new(MAX_READINGS_PER_FRAME as u8);
+    let mut stats = Statistics::new(Instant::now().as_millis());
+    let mut next_summary = Instant::now() + SUMMARY_INTERVAL;
+    let mut host = HostForwarder::new();
+    let mut responder = Responder::new(BaudConfig::default(), Instant::now().as_millis());
     
     println!("UART receiver task started");
     
-    let mut buffer = [0u8; 1];
+    let mut buffer = [0u8; 32];
     
     loop {
-        // Read byte from UART
-        match uart_rx.read(&mut buffer) {
-            Ok(_) => {
-                let byte = buffer[0];
-                if let Some(reading) = parser.process_byte(byte) {
-                    // Valid frame received - blink LED
-                    led.set_high();
-                    
-                    stats.update(&reading);
-                    display_sensor_reading(&reading);
-                    
-                    // Print statistics every 10 frames
-                    if stats.total_frames % 10 == 0 {
-                        stats.print_summary();
+        // A read only returns once the buffer is full or the line went idle, which takes
+        // longer the slower the current rate
+        let inter_byte_timeout = Duration::from_millis(inter_byte_timeout_ms(responder.rate(), buffer.len()));
+        // Wait as long as it takes for a frame to start, but not for the rest of a started one
+        let frame_deadline = (parser.state() != &ParseState::WaitingForStart)
+            .then(|| Instant::now() + inter_byte_timeout);
+        let mut deadline = frame_deadline.map_or(next_summary, |deadline| deadline.min(next_summary));
+        if let Some(link_deadline) = responder.next_deadline() {
+            deadline = deadline.min(Instant::from_millis(link_deadline));
+        }
+        let result = with_deadline(deadline, uart.read_async(&mut buffer)).await;
+        
+        if Instant::now() >= next_summary {
+            println!("\n{}\n", stats.summary(Instant::now().as_millis()));
+            next_summary = Instant::now() + SUMMARY_INTERVAL;
+        }
+        
+        let count = match result {
+            Ok(Ok(count)) => count,
+            Ok(Err(e)) => {
+                println!("UART read error: {:?}", e);
+                Timer::after(Duration::from_millis(100)).await;
+                continue;
+            }
+            Err(TimeoutError) => {
+                if frame_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    if let Some(e) = parser.on_timeout() {
+                        println!("{}", e);
+                        stats.record_error(&e);
                     }
-                    
-                    // Keep LED on for a brief moment
-                    Timer::after(Duration::from_millis(50)).await;
-                    led.set_low();
                 }
+                // Whichever deadline it was, the responder may be due: it goes back to the
+                // safe rate once board1 was quiet for too long
+                handle_baud(&mut uart, &mut encoder, &mut parser, &mut responder, None).await;
+                continue;
             }
-            Err(e) => {
-                println!("UART read error: {:?}", e);
-                Timer::after(Duration::from_millis(100)).await;
+        };
+        
+        for &byte in &buffer[..count] {
+            let result = parser.process_byte(byte);
+            // Our replies count on from board1's frames
+            encoder.sync_counter(parser.last_counter());
+            #[cfg(feature = "auth")]
+            if let (Ok(Some(_)), Some(counter)) = (&result, parser.last_counter()) {
+                esp32c3_display_board::counter::store(counter);
+            }
+            let frame = match result {
+                Ok(frame) => frame,
+                Err(e) => {
+                    println!("{}", e);
+                    stats.record_error(&e);
+                    // Ask for the broken or replayed frame again right away
+                    if let Some(nack) = receiver.on_error(&e) {
+                        send_reply(&mut uart, &mut encoder, &nack).await;
+                    }
+                    None
+                }
+            };
+            let Some(frame) = frame else {
+                continue;
+            };
+            // Before the negotiation, a baud frame tells the receiver board1 may have restarted
+            let received = receiver.on_frame(&frame);
+            // Echo the negotiation, the rest of the buffer is garbage after a switch
+            let event = responder.on_frame(&frame, Instant::now().as_millis());
+            if handle_baud(&mut uart, &mut encoder, &mut parser, &mut responder, event).await {
+                break;
+            }
+            // Only data frames are answered
+            let Some(received) = received else {
+                continue;
+            };
+            send_reply(&mut uart, &mut encoder, &received.reply).await;
+            
+            if received.duplicate {
+                println!("Duplicate frame {}, acknowledged again", received.reply.seq());
+                stats.record_duplicate();
+                continue;
+            }
+            let Frame::Data { readings, .. } = frame else {
+                continue;
+            };
+            
+            // Valid frame received - blink LED
+            BLINK.signal(());
+            
+            stats.record_frame(&readings);
+            for reading in &readings {
+                display_sensor_reading(reading);
+                host.forward(reading);
             }
         }
     }
 }
 
+async fn send_reply(uart: &mut Uart<'static, Async>, encoder: &mut DataFrame, frame: &Frame) {
+    if encoder.build_frame(frame).is_ok() {
+        if let Err(e) = uart.write_all(encoder.get_bytes()).await {
+            println!("UART write error: {:?}", e);
+        }
+    }
+}
 
-fn display_sensor_reading(reading: &SensorReading) {
-    let sensor_name = match reading.sensor_id {
-        SENSOR_HUMIDITY => "Humidity",
-        _ => "Unknown",
-    };
-    
-    let unit = match reading.sensor_id {
-        SENSOR_HUMIDITY => "%",
-        _ => "",
-    };
+// Carry out what the responder asks for, returns whether the baud rate changed
+async fn handle_baud(
+    uart: &mut Uart<'static, Async>,
+    encoder: &mut DataFrame,
+    parser: &mut FrameParser,
+    responder: &mut Responder,
+    mut event: Option<BaudEvent>
+) -> bool {
+    let mut switched = false;
+    while let Some(next) = event.take().or_else(|| responder.poll(Instant::now().as_millis())) {
+        match next {
+            BaudEvent::Transmit(frame) => send_reply(uart, encoder, &frame).await,
+            BaudEvent::SwitchTo(rate) => {
+                // The echo still goes out at the old rate
+                _ = uart.flush_async().await;
+                if let Err(e) = uart.apply_config(&Config::default().with_baudrate(rate)) {
+                    println!("UART config error: {:?}", e);
+                }
+                parser.reset();
+                switched = true;
+                println!("Switched to {} baud", rate);
+            }
+            BaudEvent::Settled(_) => {}
+        }
+    }
+    switched
+}
+
+/// Queues readings for the host, counting what doesn't fit when the host isn't reading.
+struct HostForwarder {
+    dropped: u32,
+    reported_dropped: u32,
+}
+
+impl HostForwarder {
+    fn new() -> Self {
+        Self {
+            dropped: 0,
+            reported_dropped: 0,
+        }
+    }
     
-    let icon = match reading.sensor_id {
-        SENSOR_HUMIDITY => "💧",
-        _ => "❓",
-    };
+    fn forward(&mut self, reading: &SensorReading) {
+        if self.dropped != self.reported_dropped
+            && TO_HOST.try_send(Message::Dropped(self.dropped)).is_ok()
+        {
+            self.reported_dropped = self.dropped;
+        }
+        
+        let message = Message::SensorReading(common::SensorReading {
+            sensor_id: reading.sensor_id,
+            value: reading.value,
+            timestamp: reading.timestamp,
+        });
+        if TO_HOST.try_send(message).is_err() {
+            self.dropped = self.dropped.wrapping_add(1);
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn led_task(mut led: Output<'static>) {
+    loop {
+        BLINK.wait().await;
+        // Frames arriving meanwhile make one more blink, however many there are
+        led.set_high();
+        Timer::after(BLINK_DURATION).await;
+        led.set_low();
+    }
+}
+
+#[embassy_executor::task]
+async fn usb_forward_task(mut usb_tx: UsbSerialJtagTx<'static, Async>) {
+    let mut buffer = [0u8; 32];
+    let heartbeat_interval = Duration::from_millis(HEARTBEAT_INTERVAL_MS);
     
-    println!(
-        "{} {} {}: {:.2} {} [timestamp: {}]",
-        icon,
-        sensor_name,
-        reading.sensor_id,
-        reading.value,
-        unit,
-        reading.timestamp
-    );
-}
\ No newline at end of file
+    loop {
+        // Heartbeats tell the host we are there while board1 is quiet
+        let message = with_timeout(heartbeat_interval, TO_HOST.receive())
+            .await
+            .unwrap_or(Message::Heartbeat);
+        match postcard::to_slice_cobs(&message, &mut buffer) {
+            Ok(frame) => {
+                _ = usb_tx.write_all(frame).await;
+                _ = usb_tx.flush().await;
+            }
+            Err(e) => println!("Couldn't serialize message for the host: {:?}", e),
+        }
+    }
+}
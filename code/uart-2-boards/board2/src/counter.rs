@@ -0,0 +1,37 @@
+//! The counter of the last authenticated frame accepted, kept across resets.
+//!
+//! It lives in RTC fast RAM, which a reset or a panic leaves alone, so board1's frames from
+//! before one stay replays after it. A power cut clears it, see `protocol::auth`.
+
+use esp_hal::{ram, Persistable};
+
+/// Marks a counter that was stored, not whatever was in RAM at power-on.
+const MAGIC: u32 = 0x4C41_5354;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Record {
+    magic: u32,
+    counter: u32,
+    /// The counter's complement
+    check: u32,
+}
+
+// SAFETY: Only integers, any bit pattern is a valid (if not meaningful) record
+unsafe impl Persistable for Record {}
+
+#[ram(rtc_fast, persistent)]
+static mut RECORD: Record = Record { magic: 0, counter: 0, check: 0 };
+
+/// The counter stored before the last reset, `None` after a power-on.
+pub fn load() -> Option<u32> {
+    // SAFETY: Only the UART receive task touches the record
+    let record = unsafe { (&raw const RECORD).read_volatile() };
+    (record.magic == MAGIC && record.check == !record.counter).then_some(record.counter)
+}
+
+pub fn store(counter: u32) {
+    let record = Record { magic: MAGIC, counter, check: !counter };
+    // SAFETY: Only the UART receive task touches the record
+    unsafe { (&raw mut RECORD).write_volatile(record) };
+}
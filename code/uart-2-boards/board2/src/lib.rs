@@ -1 +1,33 @@
 #![no_std]
+
+// Shared by the point-to-point and the bus firmware
+
+#[cfg(feature = "auth")]
+pub mod counter;
+
+use esp_println::println;
+use protocol::{sensor_info, SensorReading};
+
+pub fn display_sensor_reading(reading: &SensorReading) {
+    let Some(info) = sensor_info(reading.sensor_id) else {
+        println!(
+            "❓ Unknown {}: {:.2} [timestamp: {}]",
+            reading.sensor_id, reading.value, reading.timestamp
+        );
+        return;
+    };
+    
+    let warning = if info.in_range(reading.value) { "" } else { " ⚠️ out of range" };
+    
+    println!(
+        "{} {} {}: {:.*} {} [timestamp: {}]{}",
+        info.icon,
+        info.name,
+        reading.sensor_id,
+        info.precision as usize,
+        reading.value,
+        info.unit,
+        reading.timestamp,
+        warning
+    );
+}
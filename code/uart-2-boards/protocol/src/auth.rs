@@ -0,0 +1,370 @@
+//! Authenticated frames, for links that leave the enclosure.
+//!
+//! Both boards share a secret [`Key`]. Every frame then carries a counter and a tag right after
+//! its payload, see [`crate::AUTH_LENGTH`]. The tag is a SipHash-2-4 over everything from the version
+//! up to and including the counter, truncated to [`TAG_LENGTH`] bytes: without the key nobody
+//! can make a frame the receiver accepts.
+//!
+//! The counter protects against replays: a frame is only accepted with a counter higher than
+//! every frame accepted before (see [`crate::FrameParser::last_counter`]). Both sides count on
+//! from the highest counter they have seen (see [`crate::DataFrame::sync_counter`]), so the
+//! counters keep going up in both directions. A restarted sender starts over at zero, its frames
+//! look replayed. The receiver answers them with a nack, which tells the sender where to count
+//! on from, and the retransmission gets through.
+//! The counter has 32 bits, at ten frames a second that's more than a decade.
+//!
+//! A restarted receiver would accept every recorded frame again, so it has to keep its counter
+//! and hand it to [`crate::FrameParser::resume_counter`]. Board2 keeps it in RAM that survives
+//! resets and panics, but not a power cut: after one, frames from before it can be replayed
+//! until board1 sent a newer one. Keeping it in flash would close that too, at a write per frame.
+//!
+//! Only the point-to-point link uses this so far. On the bus a restarted master would never catch
+//! up, nodes don't answer frames they reject.
+//!
+//! A 32 bit tag is a 1 in 4 billion chance per forged frame. At 115200 baud that's months of
+//! trying, and every failed attempt shows up in the [`crate::Statistics`].
+
+use core::hash::Hasher;
+
+use siphasher::sip::SipHasher24;
+
+use crate::TAG_LENGTH;
+
+/// Secret shared by both ends of the link
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Key([u8; 16]);
+
+impl Key {
+    pub const fn new(bytes: [u8; 16]) -> Self {
+        Self(bytes)
+    }
+
+    /// Key from 32 hex digits, for keys given at build time:
+    ///
+    /// ```
+    /// const KEY: protocol::Key = protocol::Key::from_hex("000102030405060708090a0b0c0d0e0f");
+    /// ```
+    ///
+    /// Panics on anything else, which fails the build when used in a `const`.
+    pub const fn from_hex(hex: &str) -> Self {
+        let hex = hex.as_bytes();
+        if hex.len() != 32 {
+            panic!("key must be 32 hex digits");
+        }
+        let mut bytes = [0u8; 16];
+        let mut i = 0;
+        while i < 16 {
+            bytes[i] = hex_digit(hex[2 * i]) << 4 | hex_digit(hex[2 * i + 1]);
+            i += 1;
+        }
+        Self(bytes)
+    }
+
+    /// Tag of a frame, `parts` together are everything from the version to the counter.
+    pub(crate) fn tag(&self, parts: &[&[u8]]) -> [u8; TAG_LENGTH] {
+        let mut hasher = SipHasher24::new_with_key(&self.0);
+        for part in parts {
+            hasher.write(part);
+        }
+        let mut tag = [0u8; TAG_LENGTH];
+        tag.copy_from_slice(&hasher.finish().to_be_bytes()[..TAG_LENGTH]);
+        tag
+    }
+
+    /// Whether `tag` is right, taking the same time no matter where it differs.
+    pub(crate) fn verify(&self, parts: &[&[u8]], tag: &[u8]) -> bool {
+        let expected = self.tag(parts);
+        tag.len() == TAG_LENGTH
+            && expected
+                .iter()
+                .zip(tag)
+                .fold(0, |difference, (a, b)| difference | (a ^ b))
+                == 0
+    }
+}
+
+/// Keeps the key out of logs
+impl core::fmt::Debug for Key {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Key(..)")
+    }
+}
+
+const fn hex_digit(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        b'A'..=b'F' => digit - b'A' + 10,
+        _ => panic!("key must be 32 hex digits"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::{
+        test_reading, DataFrame, Frame, FrameError, FrameParser, Readings, SensorReading,
+        AUTH_LENGTH,
+    };
+
+    const KEY: Key = Key::from_hex("000102030405060708090a0b0c0d0e0f");
+    const OTHER_KEY: Key = Key::from_hex("F0E1D2C3B4A5968778695A4B3C2D1E0F");
+
+    fn data(seq: u8) -> Frame {
+        Frame::Data {
+            seq,
+            readings: Readings::from_slice(&[test_reading(seq as u32)]).unwrap(),
+        }
+    }
+
+    fn feed(parser: &mut FrameParser, bytes: &[u8]) -> Result<Option<Frame>, FrameError> {
+        let mut result = Ok(None);
+        for &byte in bytes {
+            result = parser.process_byte(byte);
+            if result != Ok(None) {
+                break;
+            }
+        }
+        result
+    }
+
+    fn encode(
+        encoder: &mut DataFrame,
+        frame: &Frame,
+    ) -> heapless::Vec<u8, { crate::MAX_FRAME_SIZE }> {
+        encoder.build_frame(frame).unwrap();
+        heapless::Vec::from_slice(encoder.get_bytes()).unwrap()
+    }
+
+    /// Body and CRC of an encoded frame, without the byte stuffing
+    fn unstuff(bytes: &[u8]) -> std::vec::Vec<u8> {
+        let mut body = std::vec::Vec::new();
+        let mut escaped = false;
+        for &byte in &bytes[1..bytes.len() - 1] {
+            match (escaped, byte) {
+                (false, crate::ESCAPE_BYTE) => escaped = true,
+                (false, _) => body.push(byte),
+                (true, _) => {
+                    body.push(byte ^ crate::ESCAPE_XOR);
+                    escaped = false;
+                }
+            }
+        }
+        body
+    }
+
+    fn stuff(body: &[u8]) -> std::vec::Vec<u8> {
+        let mut bytes = std::vec![crate::START_BYTE];
+        for &byte in body {
+            if matches!(
+                byte,
+                crate::START_BYTE | crate::END_BYTE | crate::ESCAPE_BYTE
+            ) {
+                bytes.extend([crate::ESCAPE_BYTE, byte ^ crate::ESCAPE_XOR]);
+            } else {
+                bytes.push(byte);
+            }
+        }
+        bytes.push(crate::END_BYTE);
+        bytes
+    }
+
+    #[test]
+    fn hex_keys() {
+        assert_eq!(
+            Key::from_hex("000102030405060708090A0B0C0D0E0F"),
+            Key::new(core::array::from_fn(|i| i as u8))
+        );
+        assert_eq!(KEY, Key::new(core::array::from_fn(|i| i as u8)));
+    }
+
+    #[test]
+    #[should_panic(expected = "32 hex digits")]
+    fn short_key() {
+        Key::from_hex("0001");
+    }
+
+    #[test]
+    #[should_panic(expected = "32 hex digits")]
+    fn key_with_invalid_digit() {
+        Key::from_hex("000102030405060708090a0b0c0d0e0g");
+    }
+
+    #[test]
+    fn tag_is_siphash_2_4() {
+        // First vector of the SipHash paper's reference implementation, empty message
+        let tag = KEY.tag(&[]);
+        assert_eq!(tag, [0x72, 0x6f, 0xdb, 0x47][..TAG_LENGTH]);
+        // Split messages hash like the whole
+        assert_eq!(KEY.tag(&[b"ab", b"", b"cde"]), KEY.tag(&[b"abcde"]));
+    }
+
+    #[test]
+    fn authenticated_frames_survive_round_trip() {
+        let mut encoder = DataFrame::authenticated(KEY);
+        let mut parser = FrameParser::authenticated(KEY);
+        let frames = [
+            data(1),
+            Frame::Ack {
+                seq: 1,
+                max_readings: 4,
+            },
+            Frame::Nack { seq: 2 },
+        ];
+
+        for (counter, frame) in (1..).zip(frames) {
+            let bytes = encode(&mut encoder, &frame);
+            assert_eq!(feed(&mut parser, &bytes), Ok(Some(frame)));
+            assert_eq!(parser.last_counter(), Some(counter));
+        }
+    }
+
+    #[test]
+    fn tampered_frames_are_rejected() {
+        let mut encoder = DataFrame::authenticated(KEY);
+        let bytes = encode(&mut encoder, &data(1));
+        let plain = encode(&mut DataFrame::new(), &data(1));
+
+        // Wrong key
+        let mut parser = FrameParser::authenticated(OTHER_KEY);
+        assert_eq!(
+            feed(&mut parser, &bytes),
+            Err(FrameError::BadTag { seq: 1 })
+        );
+        assert_eq!(parser.last_counter(), None);
+
+        // Without a key at all
+        let mut parser = FrameParser::authenticated(KEY);
+        assert_eq!(feed(&mut parser, &plain), Err(FrameError::MissingTag));
+        let mut parser = FrameParser::new();
+        assert_eq!(feed(&mut parser, &bytes), Err(FrameError::UnexpectedTag));
+
+        // Changed after the tag was made, with the CRC fixed up
+        let mut forged = unstuff(&bytes);
+        // The value of the reading
+        forged[7] ^= 0x01;
+        let crc_at = forged.len() - 2;
+        let crc = crate::crc16(&forged[..crc_at]);
+        forged[crc_at..].copy_from_slice(&crc.to_be_bytes());
+        let forged = stuff(&forged);
+        let mut parser = FrameParser::authenticated(KEY);
+        assert_eq!(
+            feed(&mut parser, &forged),
+            Err(FrameError::BadTag { seq: 1 })
+        );
+        assert_eq!(feed(&mut parser, &bytes), Ok(Some(data(1))));
+    }
+
+    #[test]
+    fn replays_are_rejected() {
+        let mut encoder = DataFrame::authenticated(KEY);
+        let mut parser = FrameParser::authenticated(KEY);
+        let first = encode(&mut encoder, &data(1));
+        let second = encode(&mut encoder, &data(2));
+
+        assert_eq!(feed(&mut parser, &second), Ok(Some(data(2))));
+        assert_eq!(
+            feed(&mut parser, &first),
+            Err(FrameError::Replayed { seq: 1, counter: 1 })
+        );
+        assert_eq!(
+            feed(&mut parser, &second),
+            Err(FrameError::Replayed { seq: 2, counter: 2 })
+        );
+        assert_eq!(parser.last_counter(), Some(2));
+    }
+
+    #[test]
+    fn restarted_sender_catches_up_through_the_nack() {
+        let mut sender_encoder = DataFrame::authenticated(KEY);
+        let mut sender_parser = FrameParser::authenticated(KEY);
+        let mut receiver_encoder = DataFrame::authenticated(KEY);
+        let mut receiver_parser = FrameParser::authenticated(KEY);
+        let receiver = crate::Receiver::new(1);
+
+        for seq in 0..5 {
+            let bytes = encode(&mut sender_encoder, &data(seq));
+            assert!(feed(&mut receiver_parser, &bytes).unwrap().is_some());
+            receiver_encoder.sync_counter(receiver_parser.last_counter());
+            let ack = encode(
+                &mut receiver_encoder,
+                &Frame::Ack {
+                    seq,
+                    max_readings: 1,
+                },
+            );
+            assert!(feed(&mut sender_parser, &ack).unwrap().is_some());
+            sender_encoder.sync_counter(sender_parser.last_counter());
+        }
+        // Both sides count on from each other
+        assert_eq!(sender_encoder.counter(), 10);
+
+        // The sender restarts and starts over
+        let mut sender_encoder = DataFrame::authenticated(KEY);
+        let mut sender_parser = FrameParser::authenticated(KEY);
+        let bytes = encode(&mut sender_encoder, &data(0));
+        let error = feed(&mut receiver_parser, &bytes).unwrap_err();
+        assert_eq!(error, FrameError::Replayed { seq: 0, counter: 1 });
+
+        let nack = receiver.on_error(&error).unwrap();
+        let nack = encode(&mut receiver_encoder, &nack);
+        assert_eq!(
+            feed(&mut sender_parser, &nack),
+            Ok(Some(Frame::Nack { seq: 0 }))
+        );
+        sender_encoder.sync_counter(sender_parser.last_counter());
+        let again = encode(&mut sender_encoder, &data(0));
+        assert_eq!(feed(&mut receiver_parser, &again), Ok(Some(data(0))));
+    }
+
+    #[test]
+    fn restarted_receiver_keeps_rejecting_recorded_frames() {
+        let mut encoder = DataFrame::authenticated(KEY);
+        let mut parser = FrameParser::authenticated(KEY);
+        let recorded = encode(&mut encoder, &data(1));
+        assert_eq!(feed(&mut parser, &recorded), Ok(Some(data(1))));
+        let kept = parser.last_counter();
+
+        // Starting over from nothing, the recording gets through again
+        assert_eq!(
+            feed(&mut FrameParser::authenticated(KEY), &recorded),
+            Ok(Some(data(1)))
+        );
+
+        let mut parser = FrameParser::authenticated(KEY);
+        parser.resume_counter(kept);
+        assert_eq!(
+            feed(&mut parser, &recorded),
+            Err(FrameError::Replayed { seq: 1, counter: 1 })
+        );
+        let next = encode(&mut encoder, &data(2));
+        assert_eq!(feed(&mut parser, &next), Ok(Some(data(2))));
+        // An older counter doesn't take it back
+        parser.resume_counter(kept);
+        assert_eq!(parser.last_counter(), Some(2));
+    }
+
+    #[test]
+    fn worst_case_authenticated_frame_fits() {
+        let reading = SensorReading {
+            sensor_id: crate::START_BYTE,
+            value: f32::from_le_bytes([crate::END_BYTE; 4]),
+            timestamp: u32::from_le_bytes([crate::ESCAPE_BYTE; 4]),
+        };
+        let frame = Frame::Data {
+            seq: crate::START_BYTE,
+            readings: Readings::from_slice(&[reading; crate::MAX_READINGS_PER_FRAME]).unwrap(),
+        };
+        let mut encoder = DataFrame::authenticated(KEY);
+        // Counter and tag full of bytes that need escaping too
+        encoder.sync_counter(Some(u32::from_be_bytes([crate::START_BYTE; 4]) - 1));
+        let bytes = encode(&mut encoder, &frame);
+        assert!(bytes.len() > crate::MAX_FRAME_SIZE - 2 * AUTH_LENGTH);
+        assert_eq!(
+            feed(&mut FrameParser::authenticated(KEY), &bytes),
+            Ok(Some(frame))
+        );
+    }
+}
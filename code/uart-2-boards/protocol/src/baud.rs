@@ -0,0 +1,802 @@
+//! Baud rate negotiation on the point-to-point link, so the same firmware runs fast over short
+//! wires and still gets through over long ones.
+//!
+//! Both boards start at [`SAFE_BAUD_RATE`]. The [`Negotiator`] (board1) proposes the next rate
+//! of [`BAUD_RATES`] with a [`Frame::Baud`], the [`Responder`] (board2) echoes it and both
+//! switch. At the new rate the negotiator sends [`BaudConfig::probes`] [`Frame::Probe`]s, one
+//! at a time, which the responder echoes. If no more than [`BaudConfig::max_lost_probes`] of
+//! them go unanswered the rate is kept and the next one is tried. Otherwise both go back to the
+//! last good rate, which becomes the ceiling: nothing faster is tried again.
+//!
+//! Once settled the negotiator watches the data frames. When none went out for
+//! [`BaudConfig::keepalive_ms`] it sends a [`Frame::Baud`] with the current rate, so the
+//! responder doesn't take a quiet sender for a lost one. [`BaudConfig::max_failures`]
+//! retransmissions in a row, mostly nacks for frames that failed their CRC, make it propose
+//! the rate one step down, which becomes the new ceiling.
+//!
+//! An unanswered [`Frame::Baud`] means the boards might not be at the same rate anymore: a
+//! switch only reached one of them, or one restarted. Then both go back to the safe rate. The
+//! responder does so after hearing nothing valid for [`BaudConfig::link_timeout_ms`], the
+//! negotiator keeps quiet for longer than that and starts over.
+//!
+//! The bus always runs at one fixed rate, this is only for the two boards.
+
+use crate::{schedule::uart_bytes_per_second, Frame, INTER_BYTE_TIMEOUT_MS};
+
+/// The rates tried, slowest first.
+pub const BAUD_RATES: [u32; 8] = [
+    9_600, 19_200, 38_400, 57_600, 115_200, 230_400, 460_800, 921_600,
+];
+
+/// Where both boards start, and where they meet again after losing each other.
+pub const SAFE_BAUD_RATE: u32 = BAUD_RATES[0];
+
+/// Milliseconds `bytes` take on the wire at `baud_rate`, rounded up.
+pub const fn transfer_time_ms(bytes: usize, baud_rate: u32) -> u64 {
+    let bytes_per_second = uart_bytes_per_second(baud_rate) as u64;
+    let bytes_per_second = if bytes_per_second == 0 {
+        1
+    } else {
+        bytes_per_second
+    };
+    (bytes as u64 * 1000).div_ceil(bytes_per_second)
+}
+
+/// Byte times of silence after which the UART hands over what it received so far, the RX
+/// timeout esp-hal configures by default.
+pub const RX_IDLE_SYMBOLS: usize = 10;
+
+/// Longest pause between two reads of the same frame for a receiver reading up to `read_size`
+/// bytes at a time: a full read and the UART's idle time before it returns, on top of
+/// [`INTER_BYTE_TIMEOUT_MS`]. Changes with the rate, at 9600 baud a read of 32 bytes alone
+/// takes over 40 ms.
+pub const fn inter_byte_timeout_ms(baud_rate: u32, read_size: usize) -> u64 {
+    transfer_time_ms(read_size + RX_IDLE_SYMBOLS, baud_rate) + INTER_BYTE_TIMEOUT_MS
+}
+
+/// Limits and timing of the negotiation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BaudConfig {
+    /// Fastest rate this board proposes or accepts.
+    pub max_rate: u32,
+    /// Probes sent to try a rate.
+    pub probes: u8,
+    /// How many probes may go unanswered before a rate counts as too fast.
+    pub max_lost_probes: u8,
+    /// How long the negotiator waits for the echo of a baud frame or a probe.
+    pub answer_timeout_ms: u64,
+    /// How often a baud frame is sent before the responder counts as lost.
+    pub max_attempts: u8,
+    /// Time for the responder to switch after its echo, before anything is sent at the new
+    /// rate.
+    pub settle_ms: u64,
+    /// Retransmissions of data frames in a row before stepping down.
+    pub max_failures: u8,
+    /// Silence after which the responder goes back to the safe rate. The negotiator has to
+    /// send more often than that.
+    pub link_timeout_ms: u64,
+    /// Time without data frames after which the settled negotiator sends a baud frame with the
+    /// current rate, well below [`BaudConfig::link_timeout_ms`].
+    pub keepalive_ms: u64,
+}
+
+impl BaudConfig {
+    pub const fn new() -> Self {
+        Self {
+            max_rate: BAUD_RATES[BAUD_RATES.len() - 1],
+            probes: 20,
+            max_lost_probes: 1,
+            // A probe and its echo take about 75 ms at the safe rate, authenticated
+            answer_timeout_ms: 150,
+            max_attempts: 3,
+            settle_ms: 20,
+            max_failures: 6,
+            link_timeout_ms: 2000,
+            keepalive_ms: 500,
+        }
+    }
+}
+
+impl Default for BaudConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What the [`Negotiator`] or [`Responder`] wants the caller to do.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BaudEvent {
+    /// Put this frame on the wire at the current rate.
+    Transmit(Frame),
+    /// Wait until everything written went out, then reconfigure the UART to this rate.
+    SwitchTo(u32),
+    /// The negotiation is over, data frames can go out at this rate. Only from the negotiator.
+    Settled(u32),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum State {
+    Settled {
+        /// When to show the responder we are still there, unless data frames do
+        keepalive: u64,
+    },
+    Proposing {
+        rate: u32,
+        /// Stepping down after failures, the rate becomes the ceiling once accepted
+        lowering: bool,
+        attempts: u8,
+        deadline: u64,
+    },
+    Probing {
+        /// Where to go back to if the probes fail
+        fallback: u32,
+        sent: u8,
+        lost: u8,
+        echoed: bool,
+        deadline: u64,
+    },
+    /// Lost the responder, waiting for it to fall back to the safe rate
+    Resetting { until: u64 },
+}
+
+/// Negotiating side, on the board sending the data.
+pub struct Negotiator {
+    config: BaudConfig,
+    rate: u32,
+    ceiling: u32,
+    failures: u8,
+    state: State,
+    queued: Option<BaudEvent>,
+}
+
+impl Negotiator {
+    /// Starts at the safe rate, the first proposal goes out on the first [`Negotiator::poll`].
+    pub fn new(config: BaudConfig, now_ms: u64) -> Self {
+        let mut negotiator = Self {
+            config,
+            rate: SAFE_BAUD_RATE,
+            ceiling: config.max_rate,
+            failures: 0,
+            state: State::Settled { keepalive: now_ms },
+            queued: None,
+        };
+        negotiator.queued = negotiator.step_up(now_ms);
+        negotiator
+    }
+
+    /// The rate the UART is supposed to run at.
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Whether data frames may go out, they would only disturb a negotiation.
+    pub fn is_settled(&self) -> bool {
+        matches!(self.state, State::Settled { .. })
+    }
+
+    /// Handle a frame received from the responder, anything but baud frames and probes is
+    /// ignored.
+    pub fn on_frame(&mut self, frame: &Frame, now_ms: u64) -> Option<BaudEvent> {
+        if self.queued.is_some() {
+            // Answers from before a switch the caller hasn't made yet
+            return None;
+        }
+        match (frame, self.state) {
+            (&Frame::Baud { rate: answer }, State::Proposing { rate, lowering, .. }) => {
+                if answer != rate {
+                    // Too fast for the responder
+                    self.ceiling = self.rate;
+                    return self.step_up(now_ms);
+                }
+                if lowering {
+                    self.ceiling = rate;
+                }
+                if rate == self.rate {
+                    // Confirmed the responder is still with us
+                    return self.step_up(now_ms);
+                }
+
+                let fallback = if lowering {
+                    next_rate_down(rate).unwrap_or(SAFE_BAUD_RATE)
+                } else {
+                    self.rate
+                };
+                self.rate = rate;
+                self.state = State::Probing {
+                    fallback,
+                    sent: 0,
+                    lost: 0,
+                    echoed: false,
+                    deadline: now_ms + self.config.settle_ms,
+                };
+                Some(BaudEvent::SwitchTo(rate))
+            }
+            (
+                &Frame::Probe { seq },
+                State::Probing {
+                    fallback,
+                    sent,
+                    lost,
+                    echoed: false,
+                    ..
+                },
+            ) if seq.wrapping_add(1) == sent => {
+                self.state = State::Probing {
+                    fallback,
+                    sent,
+                    lost,
+                    echoed: true,
+                    deadline: now_ms,
+                };
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// A data frame went out, which shows the responder we are there just as well as a
+    /// keepalive.
+    pub fn on_sent(&mut self, now_ms: u64) {
+        if let State::Settled { keepalive } = &mut self.state {
+            *keepalive = now_ms + self.config.keepalive_ms;
+        }
+    }
+
+    /// A data frame was acknowledged.
+    pub fn on_delivered(&mut self) {
+        self.failures = 0;
+    }
+
+    /// A data frame had to be sent again or was given up, steps down after too many in a row.
+    pub fn on_failure(&mut self, now_ms: u64) {
+        if !self.is_settled() {
+            return;
+        }
+        self.failures = self.failures.saturating_add(1);
+        if self.failures < self.config.max_failures {
+            return;
+        }
+        self.failures = 0;
+        // Nothing to do about a line that doesn't even work at the safe rate
+        if let Some(rate) = next_rate_down(self.rate) {
+            self.state = State::Proposing {
+                rate,
+                lowering: true,
+                attempts: 0,
+                deadline: now_ms,
+            };
+        }
+    }
+
+    /// Let time pass: proposes rates, sends probes and gives up on missing answers. Call it
+    /// until it returns `None`.
+    pub fn poll(&mut self, now_ms: u64) -> Option<BaudEvent> {
+        if let Some(event) = self.queued.take() {
+            if let BaudEvent::SwitchTo(rate) = event {
+                self.rate = rate;
+            }
+            return Some(event);
+        }
+        if now_ms < self.next_deadline()? {
+            return None;
+        }
+
+        match self.state {
+            State::Settled { .. } => {
+                // Not waiting for the echo, the data frames notice a lost responder
+                self.state = State::Settled {
+                    keepalive: now_ms + self.config.keepalive_ms,
+                };
+                Some(BaudEvent::Transmit(Frame::Baud { rate: self.rate }))
+            }
+            State::Proposing {
+                rate,
+                lowering,
+                attempts,
+                ..
+            } => {
+                if attempts >= self.config.max_attempts {
+                    return self.reset(now_ms);
+                }
+                self.state = State::Proposing {
+                    rate,
+                    lowering,
+                    attempts: attempts + 1,
+                    deadline: now_ms + self.config.answer_timeout_ms,
+                };
+                Some(BaudEvent::Transmit(Frame::Baud { rate }))
+            }
+            State::Probing {
+                fallback,
+                sent,
+                mut lost,
+                echoed,
+                ..
+            } => {
+                if sent > 0 && !echoed {
+                    lost += 1;
+                }
+                if lost > self.config.max_lost_probes {
+                    return Some(self.fall_back(fallback, now_ms));
+                }
+                if sent >= self.config.probes {
+                    return self.step_up(now_ms).or_else(|| self.poll(now_ms));
+                }
+                self.state = State::Probing {
+                    fallback,
+                    sent: sent + 1,
+                    lost,
+                    echoed: false,
+                    deadline: now_ms + self.config.answer_timeout_ms,
+                };
+                Some(BaudEvent::Transmit(Frame::Probe { seq: sent }))
+            }
+            State::Resetting { .. } => self.step_up(now_ms).or_else(|| self.poll(now_ms)),
+        }
+    }
+
+    /// When [`Negotiator::poll`] has to be called next, once it returned `None`. When settled
+    /// that's the next keepalive.
+    pub fn next_deadline(&self) -> Option<u64> {
+        match self.state {
+            State::Settled { keepalive } => Some(keepalive),
+            State::Proposing { deadline, .. } | State::Probing { deadline, .. } => Some(deadline),
+            State::Resetting { until } => Some(until),
+        }
+    }
+
+    /// Propose the next faster rate, or settle at the current one.
+    fn step_up(&mut self, now_ms: u64) -> Option<BaudEvent> {
+        let next = BAUD_RATES
+            .iter()
+            .copied()
+            .find(|&rate| rate > self.rate && rate <= self.ceiling);
+        match next {
+            Some(rate) => {
+                self.state = State::Proposing {
+                    rate,
+                    lowering: false,
+                    attempts: 0,
+                    deadline: now_ms,
+                };
+                None
+            }
+            None => {
+                self.state = State::Settled {
+                    keepalive: now_ms + self.config.keepalive_ms,
+                };
+                Some(BaudEvent::Settled(self.rate))
+            }
+        }
+    }
+
+    /// The probes failed: tell the responder to go back (it may not hear it), go back and
+    /// make sure it did.
+    fn fall_back(&mut self, fallback: u32, now_ms: u64) -> BaudEvent {
+        self.ceiling = fallback;
+        self.queued = Some(BaudEvent::SwitchTo(fallback));
+        self.state = State::Proposing {
+            rate: fallback,
+            lowering: false,
+            attempts: 0,
+            deadline: now_ms + self.config.settle_ms,
+        };
+        BaudEvent::Transmit(Frame::Baud { rate: fallback })
+    }
+
+    fn reset(&mut self, now_ms: u64) -> Option<BaudEvent> {
+        self.state = State::Resetting {
+            until: now_ms + self.config.link_timeout_ms + self.config.answer_timeout_ms,
+        };
+        if self.rate == SAFE_BAUD_RATE {
+            return None;
+        }
+        self.rate = SAFE_BAUD_RATE;
+        Some(BaudEvent::SwitchTo(SAFE_BAUD_RATE))
+    }
+}
+
+/// Answering side, on the board receiving the data.
+pub struct Responder {
+    config: BaudConfig,
+    rate: u32,
+    last_heard: u64,
+    queued: Option<BaudEvent>,
+}
+
+impl Responder {
+    /// Starts at the safe rate.
+    pub fn new(config: BaudConfig, now_ms: u64) -> Self {
+        Self {
+            config,
+            rate: SAFE_BAUD_RATE,
+            last_heard: now_ms,
+            queued: None,
+        }
+    }
+
+    /// The rate the UART is supposed to run at.
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Handle any valid frame received: baud frames and probes are answered, the others only
+    /// show the link is alive.
+    pub fn on_frame(&mut self, frame: &Frame, now_ms: u64) -> Option<BaudEvent> {
+        self.last_heard = now_ms;
+        match *frame {
+            Frame::Baud { rate } => {
+                if !BAUD_RATES.contains(&rate) || rate > self.config.max_rate {
+                    // Refused, the negotiator stays where we are
+                    return Some(BaudEvent::Transmit(Frame::Baud { rate: self.rate }));
+                }
+                // The echo still goes out at the old rate
+                if rate != self.rate {
+                    self.queued = Some(BaudEvent::SwitchTo(rate));
+                }
+                Some(BaudEvent::Transmit(Frame::Baud { rate }))
+            }
+            Frame::Probe { seq } => Some(BaudEvent::Transmit(Frame::Probe { seq })),
+            _ => None,
+        }
+    }
+
+    /// Let time pass: switches after an echo, and back to the safe rate when the negotiator
+    /// went quiet. Call it until it returns `None`.
+    pub fn poll(&mut self, now_ms: u64) -> Option<BaudEvent> {
+        if let Some(event) = self.queued.take() {
+            if let BaudEvent::SwitchTo(rate) = event {
+                self.rate = rate;
+            }
+            return Some(event);
+        }
+        if now_ms < self.next_deadline()? {
+            return None;
+        }
+        self.rate = SAFE_BAUD_RATE;
+        self.last_heard = now_ms;
+        Some(BaudEvent::SwitchTo(SAFE_BAUD_RATE))
+    }
+
+    /// When [`Responder::poll`] has to be called next, once it returned `None`. `None` at the
+    /// safe rate.
+    pub fn next_deadline(&self) -> Option<u64> {
+        (self.rate != SAFE_BAUD_RATE).then_some(self.last_heard + self.config.link_timeout_ms)
+    }
+}
+
+fn next_rate_down(rate: u32) -> Option<u32> {
+    BAUD_RATES.iter().rev().copied().find(|&lower| lower < rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataFrame, FrameParser, Readings, RetransmitConfig, SensorReading};
+
+    /// Whether a frame gets through at a rate, `count` numbers the frames on the line
+    type Line = fn(rate: u32, count: u32) -> bool;
+
+    fn up_to_115200(rate: u32, _: u32) -> bool {
+        rate <= 115_200
+    }
+
+    fn perfect(_: u32, _: u32) -> bool {
+        true
+    }
+
+    /// Both boards on one line. A frame only gets through when both run at the same rate and
+    /// the line lets it.
+    struct SimulatedLink {
+        negotiator: Negotiator,
+        responder: Responder,
+        line: Line,
+        count: u32,
+        now: u64,
+        settled: Option<u32>,
+        /// Whether the negotiator has data to send once settled
+        sending_data: bool,
+    }
+
+    impl SimulatedLink {
+        fn new(negotiator: BaudConfig, responder: BaudConfig, line: Line) -> Self {
+            Self {
+                negotiator: Negotiator::new(negotiator, 0),
+                responder: Responder::new(responder, 0),
+                line,
+                count: 0,
+                now: 0,
+                settled: None,
+                sending_data: true,
+            }
+        }
+
+        fn gets_through(&mut self) -> bool {
+            self.count += 1;
+            self.negotiator.rate() == self.responder.rate()
+                && (self.line)(self.negotiator.rate(), self.count)
+        }
+
+        fn run_for(&mut self, duration_ms: u64) {
+            let end = self.now + duration_ms;
+            while self.now < end {
+                while let Some(event) = self.negotiator.poll(self.now) {
+                    self.negotiator_sent(event);
+                }
+                while let Some(event) = self.responder.poll(self.now) {
+                    self.responder_sent(event);
+                }
+                if self.sending_data && self.now % 10 == 0 && self.negotiator.is_settled() {
+                    // A data frame every 10 ms, and its acknowledgement
+                    let frame = Frame::Idle;
+                    self.negotiator.on_sent(self.now);
+                    if self.gets_through() {
+                        self.responder.on_frame(&frame, self.now);
+                        if self.gets_through() {
+                            self.negotiator.on_delivered();
+                        } else {
+                            self.negotiator.on_failure(self.now);
+                        }
+                    } else {
+                        self.negotiator.on_failure(self.now);
+                    }
+                }
+                self.now += 1;
+            }
+        }
+
+        fn negotiator_sent(&mut self, event: BaudEvent) {
+            match event {
+                BaudEvent::Transmit(frame) => {
+                    if self.gets_through() {
+                        if let Some(answer) = self.responder.on_frame(&frame, self.now) {
+                            self.responder_sent(answer);
+                        }
+                    }
+                }
+                BaudEvent::SwitchTo(rate) => assert_eq!(rate, self.negotiator.rate()),
+                BaudEvent::Settled(rate) => self.settled = Some(rate),
+            }
+        }
+
+        fn responder_sent(&mut self, event: BaudEvent) {
+            match event {
+                BaudEvent::Transmit(frame) => {
+                    if self.gets_through() {
+                        if let Some(event) = self.negotiator.on_frame(&frame, self.now) {
+                            self.negotiator_sent(event);
+                        }
+                    }
+                }
+                BaudEvent::SwitchTo(rate) => assert_eq!(rate, self.responder.rate()),
+                BaudEvent::Settled(_) => panic!("the responder doesn't settle"),
+            }
+        }
+
+        fn assert_settled_at(&self, rate: u32) {
+            assert!(self.negotiator.is_settled());
+            assert_eq!(self.settled, Some(rate));
+            assert_eq!(self.negotiator.rate(), rate);
+            assert_eq!(self.responder.rate(), rate);
+        }
+    }
+
+    fn with_max_rate(max_rate: u32) -> BaudConfig {
+        BaudConfig {
+            max_rate,
+            ..BaudConfig::new()
+        }
+    }
+
+    #[test]
+    fn climbs_to_the_fastest_rate_both_support() {
+        let mut link = SimulatedLink::new(BaudConfig::new(), with_max_rate(460_800), perfect);
+        link.run_for(10_000);
+        link.assert_settled_at(460_800);
+
+        let mut link = SimulatedLink::new(with_max_rate(57_600), BaudConfig::new(), perfect);
+        link.run_for(10_000);
+        link.assert_settled_at(57_600);
+    }
+
+    #[test]
+    fn stays_below_the_rate_the_line_cannot_carry() {
+        let mut link = SimulatedLink::new(BaudConfig::new(), BaudConfig::new(), up_to_115200);
+        link.run_for(10_000);
+        link.assert_settled_at(115_200);
+
+        // One lost probe is fine, a frame in ten lost is too many
+        fn flaky_above_115200(rate: u32, count: u32) -> bool {
+            rate <= 115_200 || count % 10 != 0
+        }
+        let mut link = SimulatedLink::new(BaudConfig::new(), BaudConfig::new(), flaky_above_115200);
+        link.run_for(10_000);
+        link.assert_settled_at(115_200);
+    }
+
+    #[test]
+    fn repeated_failures_step_down() {
+        let mut link = SimulatedLink::new(BaudConfig::new(), BaudConfig::new(), perfect);
+        link.run_for(10_000);
+        link.assert_settled_at(921_600);
+
+        // The cable got worse: every other frame breaks at the top rate
+        fn flaky_at_921600(rate: u32, count: u32) -> bool {
+            rate < 921_600 || count % 2 == 0
+        }
+        link.line = flaky_at_921600;
+        link.run_for(20_000);
+        link.assert_settled_at(460_800);
+
+        // And doesn't go back up
+        link.line = perfect;
+        link.run_for(20_000);
+        link.assert_settled_at(460_800);
+    }
+
+    #[test]
+    fn boards_meet_again_after_a_restart() {
+        let mut link = SimulatedLink::new(BaudConfig::new(), BaudConfig::new(), up_to_115200);
+        link.run_for(10_000);
+        link.assert_settled_at(115_200);
+
+        // The responder restarts at the safe rate and hears nothing but garbage
+        link.responder = Responder::new(BaudConfig::new(), link.now);
+        link.run_for(20_000);
+        link.assert_settled_at(115_200);
+
+        // The negotiator restarts, the responder is still at the fast rate
+        link.negotiator = Negotiator::new(BaudConfig::new(), link.now);
+        link.settled = None;
+        link.run_for(20_000);
+        link.assert_settled_at(115_200);
+    }
+
+    #[test]
+    fn keepalives_hold_a_quiet_link_at_its_rate() {
+        let mut link = SimulatedLink::new(BaudConfig::new(), BaudConfig::new(), perfect);
+        link.run_for(10_000);
+        link.assert_settled_at(921_600);
+
+        // No data for several link timeouts
+        link.sending_data = false;
+        let config = BaudConfig::new();
+        for _ in 0..5 {
+            link.run_for(config.link_timeout_ms);
+            link.assert_settled_at(921_600);
+        }
+        // Only ever a keepalive away
+        let deadline = link.negotiator.next_deadline().unwrap();
+        assert!(deadline > link.now && deadline <= link.now + config.keepalive_ms);
+    }
+
+    #[test]
+    fn data_frames_put_the_keepalive_off() {
+        let config = BaudConfig::new();
+        let mut negotiator = Negotiator::new(with_max_rate(SAFE_BAUD_RATE), 0);
+        assert_eq!(negotiator.poll(0), Some(BaudEvent::Settled(SAFE_BAUD_RATE)));
+        assert_eq!(negotiator.next_deadline(), Some(config.keepalive_ms));
+
+        negotiator.on_sent(300);
+        assert_eq!(negotiator.poll(config.keepalive_ms), None);
+        assert_eq!(negotiator.next_deadline(), Some(300 + config.keepalive_ms));
+        assert_eq!(
+            negotiator.poll(300 + config.keepalive_ms),
+            Some(BaudEvent::Transmit(Frame::Baud {
+                rate: SAFE_BAUD_RATE
+            }))
+        );
+        assert!(negotiator.is_settled());
+        assert_eq!(
+            negotiator.next_deadline(),
+            Some(300 + 2 * config.keepalive_ms)
+        );
+    }
+
+    #[test]
+    fn responder_refuses_unknown_and_too_fast_rates() {
+        let mut responder = Responder::new(with_max_rate(115_200), 0);
+        for rate in [100_000, 230_400] {
+            assert_eq!(
+                responder.on_frame(&Frame::Baud { rate }, 0),
+                Some(BaudEvent::Transmit(Frame::Baud {
+                    rate: SAFE_BAUD_RATE
+                }))
+            );
+            assert_eq!(responder.poll(0), None);
+        }
+    }
+
+    #[test]
+    fn responder_goes_back_to_the_safe_rate_when_nothing_arrives() {
+        let mut responder = Responder::new(BaudConfig::new(), 0);
+        assert_eq!(responder.next_deadline(), None);
+        responder.on_frame(&Frame::Baud { rate: 115_200 }, 100);
+        assert_eq!(responder.poll(100), Some(BaudEvent::SwitchTo(115_200)));
+        assert_eq!(responder.poll(100), None);
+
+        // Any valid frame keeps it there
+        responder.on_frame(&Frame::Idle, 1000);
+        assert_eq!(responder.poll(2999), None);
+        assert_eq!(
+            responder.poll(3000),
+            Some(BaudEvent::SwitchTo(SAFE_BAUD_RATE))
+        );
+        assert_eq!(responder.next_deadline(), None);
+    }
+
+    #[test]
+    fn timing_follows_the_rate() {
+        assert_eq!(transfer_time_ms(96, 9600), 100);
+        assert_eq!(transfer_time_ms(1, 921_600), 1);
+        // About what has always been used at 115200 baud
+        assert!((50..60).contains(&RetransmitConfig::for_baud_rate(115_200).ack_timeout_ms));
+        assert!(RetransmitConfig::for_baud_rate(9600).ack_timeout_ms > 300);
+    }
+
+    /// When each read returns, for a frame coming in at `baud_rate` in reads of up to
+    /// `read_size` bytes: once the read is full, or after the UART's idle time at the end of
+    /// the frame. In microseconds from the start of the frame, with the bytes of each read.
+    fn reads(frame: &[u8], baud_rate: u32, read_size: usize) -> heapless::Vec<(u64, &[u8]), 16> {
+        let byte_us = 1_000_000 / uart_bytes_per_second(baud_rate) as u64;
+        let mut received = 0;
+        frame
+            .chunks(read_size)
+            .map(|chunk| {
+                received += chunk.len();
+                let mut returned = received as u64 * byte_us;
+                if chunk.len() < read_size {
+                    returned += RX_IDLE_SYMBOLS as u64 * byte_us;
+                }
+                (returned, chunk)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn frames_longer_than_a_read_survive_the_safe_rate() {
+        let reading = SensorReading {
+            sensor_id: 1,
+            value: 21.5,
+            timestamp: 7,
+        };
+        let frame = Frame::Data {
+            seq: 3,
+            readings: Readings::from_slice(&[reading; 3]).unwrap(),
+        };
+        let mut encoder = DataFrame::new();
+        encoder.build_frame(&frame).unwrap();
+        let bytes = encoder.get_bytes();
+        assert!(bytes.len() > 32);
+
+        let read_size = 32;
+        let timeout_us = inter_byte_timeout_ms(SAFE_BAUD_RATE, read_size) * 1000;
+        let mut parser = FrameParser::new();
+        let mut last_read = 0;
+        let mut parsed = None;
+        for (returned, chunk) in reads(bytes, SAFE_BAUD_RATE, read_size) {
+            // The second read takes longer than the fixed timeout alone would allow
+            if last_read > 0 {
+                assert!(returned - last_read > INTER_BYTE_TIMEOUT_MS * 1000);
+            }
+            assert!(returned - last_read <= timeout_us, "read timed out");
+            last_read = returned;
+            for &byte in chunk {
+                parsed = parser.process_byte(byte).unwrap().or(parsed);
+            }
+        }
+        assert_eq!(parsed, Some(frame));
+    }
+
+    #[test]
+    fn inter_byte_timeout_follows_the_rate() {
+        assert_eq!(inter_byte_timeout_ms(9_600, 32), 44 + INTER_BYTE_TIMEOUT_MS);
+        assert_eq!(
+            inter_byte_timeout_ms(921_600, 32),
+            1 + INTER_BYTE_TIMEOUT_MS
+        );
+        for pair in BAUD_RATES.windows(2) {
+            assert!(inter_byte_timeout_ms(pair[0], 32) >= inter_byte_timeout_ms(pair[1], 32));
+        }
+    }
+}
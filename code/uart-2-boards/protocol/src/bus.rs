@@ -0,0 +1,684 @@
+//! Multi-drop bus: one [`Master`] (board2) polls several [`Node`]s (sensor boards) on a shared
+//! half-duplex line, RS-485 style.
+//!
+//! Only the master starts a conversation. It sends a [`Frame::Poll`] to one node at a time and
+//! only that node answers: with a data frame, or [`Frame::Idle`] when it has nothing to report.
+//! Data frames are acknowledged like on the point-to-point link, and a node whose
+//! acknowledgement got lost sends the same frame again on its next poll, which the master
+//! recognizes as a duplicate. A node that missed [`BusConfig::restart_after_missed`] polls in a
+//! row probably restarted and counts its frames from zero again, so the master forgets which
+//! one it got last.
+//!
+//! Nobody drives the bus right after something arrived: both sides wait
+//! [`BusConfig::turnaround_ms`] so the other side's transceiver is back to receiving. A node
+//! that doesn't answer within [`BusConfig::response_timeout_ms`] is skipped, and the master
+//! waits another [`BusConfig::guard_ms`] before the next poll so a late answer can't collide
+//! with it.
+//!
+//! Like the stop-and-wait [`crate::Sender`] and [`crate::Receiver`] this never touches the UART
+//! or a timer.
+
+use heapless::Vec;
+
+use crate::{
+    link::Receiver, schedule::ReadingQueue, Frame, Readings, SensorReading, DIRECT_ADDRESS,
+    MAX_READINGS_PER_FRAME,
+};
+
+/// Most nodes one master polls
+pub const MAX_NODES: usize = 8;
+
+/// Readings a node keeps until it is polled
+pub const NODE_QUEUE_LENGTH: usize = 2 * MAX_READINGS_PER_FRAME;
+
+/// Timing on the bus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusConfig {
+    /// How long the master waits for a polled node, counted from the start of the poll.
+    pub response_timeout_ms: u64,
+    /// Pause after receiving before sending anything.
+    pub turnaround_ms: u64,
+    /// Extra pause after a node didn't answer.
+    pub guard_ms: u64,
+    /// Polls a node has to miss in a row before its next data frame counts as new, whatever
+    /// its sequence number. Rebooting takes far longer than that many polls.
+    pub restart_after_missed: u8,
+}
+
+impl BusConfig {
+    pub const fn new() -> Self {
+        Self {
+            // A full data frame takes about 7 ms at 115200 baud
+            response_timeout_ms: 20,
+            turnaround_ms: 1,
+            guard_ms: 5,
+            restart_after_missed: 3,
+        }
+    }
+}
+
+impl Default for BusConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Why [`Master::add_node`] refused a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BusError {
+    /// Already [`MAX_NODES`] nodes
+    TooManyNodes,
+    /// Two nodes with the same address would answer the same poll
+    DuplicateAddress(u8),
+    /// [`DIRECT_ADDRESS`] is for the point-to-point link
+    InvalidAddress(u8),
+}
+
+/// What the [`Master`] wants the caller to know or do.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MasterEvent {
+    /// Put this frame on the bus, addressed to `address`.
+    Transmit { address: u8, frame: Frame },
+    /// New readings from a node.
+    Readings { address: u8, readings: Readings },
+    /// The node didn't answer its poll, `missed` polls in a row by now.
+    NoResponse { address: u8, missed: u8 },
+}
+
+struct Slot {
+    address: u8,
+    receiver: Receiver,
+    missed: u8,
+}
+
+enum MasterState {
+    /// Poll the current node from `not_before` on
+    Idle { not_before: u64 },
+    /// Polled the current node, waiting for its answer
+    Polling { deadline: u64 },
+    /// Acknowledge the current node's data from `not_before` on
+    Acknowledging { ack: Frame, not_before: u64 },
+}
+
+/// Bus master polling its nodes round robin.
+pub struct Master {
+    config: BusConfig,
+    max_readings: u8,
+    slots: Vec<Slot, MAX_NODES>,
+    current: usize,
+    state: MasterState,
+}
+
+impl Master {
+    /// A master accepting up to `max_readings` readings per data frame, without any nodes yet.
+    pub const fn new(max_readings: u8, config: BusConfig) -> Self {
+        Self {
+            config,
+            max_readings,
+            slots: Vec::new(),
+            current: 0,
+            state: MasterState::Idle { not_before: 0 },
+        }
+    }
+
+    pub fn add_node(&mut self, address: u8) -> Result<(), BusError> {
+        if address == DIRECT_ADDRESS {
+            return Err(BusError::InvalidAddress(address));
+        }
+        if self.slots.iter().any(|slot| slot.address == address) {
+            return Err(BusError::DuplicateAddress(address));
+        }
+        self.slots
+            .push(Slot {
+                address,
+                receiver: Receiver::new(self.max_readings),
+                missed: 0,
+            })
+            .map_err(|_| BusError::TooManyNodes)
+    }
+
+    /// Handle a frame received from the bus, `address` as reported by the parser.
+    pub fn on_frame(&mut self, address: u8, frame: &Frame, now_ms: u64) -> Option<MasterEvent> {
+        if !matches!(self.state, MasterState::Polling { .. }) {
+            // Nobody was asked, probably a late answer
+            return None;
+        }
+        let slot = self.slots.get_mut(self.current)?;
+        if address != slot.address {
+            return None;
+        }
+
+        match frame {
+            Frame::Data { readings, .. } => {
+                let received = slot.receiver.on_frame(frame)?;
+                slot.missed = 0;
+                self.state = MasterState::Acknowledging {
+                    ack: received.reply,
+                    not_before: now_ms + self.config.turnaround_ms,
+                };
+                (!received.duplicate).then(|| MasterEvent::Readings {
+                    address,
+                    readings: readings.clone(),
+                })
+            }
+            Frame::Idle => {
+                slot.missed = 0;
+                self.next_node(now_ms + self.config.turnaround_ms);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Let time pass: polls the next node, acknowledges data and notices silent nodes.
+    pub fn poll(&mut self, now_ms: u64) -> Option<MasterEvent> {
+        if now_ms < self.next_deadline() || self.slots.is_empty() {
+            return None;
+        }
+        let address = self.slots[self.current].address;
+
+        let state = core::mem::replace(&mut self.state, MasterState::Idle { not_before: 0 });
+        match state {
+            MasterState::Idle { .. } => {
+                self.state = MasterState::Polling {
+                    deadline: now_ms + self.config.response_timeout_ms,
+                };
+                Some(MasterEvent::Transmit {
+                    address,
+                    frame: Frame::Poll {
+                        max_readings: self.max_readings,
+                    },
+                })
+            }
+            MasterState::Polling { .. } => {
+                let slot = &mut self.slots[self.current];
+                slot.missed = slot.missed.saturating_add(1);
+                let missed = slot.missed;
+                if missed == self.config.restart_after_missed {
+                    slot.receiver = Receiver::new(self.max_readings);
+                }
+                self.next_node(now_ms + self.config.guard_ms);
+                Some(MasterEvent::NoResponse { address, missed })
+            }
+            MasterState::Acknowledging { ack, .. } => {
+                // Nobody answers an ack, the next poll can follow right away
+                self.next_node(now_ms);
+                Some(MasterEvent::Transmit {
+                    address,
+                    frame: ack,
+                })
+            }
+        }
+    }
+
+    /// When [`Master::poll`] has to be called next.
+    pub fn next_deadline(&self) -> u64 {
+        match self.state {
+            MasterState::Idle { not_before } => not_before,
+            MasterState::Polling { deadline } => deadline,
+            MasterState::Acknowledging { not_before, .. } => not_before,
+        }
+    }
+
+    fn next_node(&mut self, not_before: u64) {
+        self.current = (self.current + 1) % self.slots.len();
+        self.state = MasterState::Idle { not_before };
+    }
+}
+
+/// What the [`Node`] wants the caller to know or do.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeEvent {
+    /// Put this frame on the bus, from this node's address.
+    Transmit(Frame),
+    /// The master acknowledged the data frame.
+    Delivered { seq: u8 },
+}
+
+/// Sensor node on the bus, answering when polled.
+pub struct Node {
+    address: u8,
+    config: BusConfig,
+    queue: ReadingQueue<NODE_QUEUE_LENGTH>,
+    next_seq: u8,
+    /// Data frame sent but not acknowledged yet
+    pending: Option<Frame>,
+    /// Answer to a poll, to be sent from the given time on
+    reply: Option<(Frame, u64)>,
+}
+
+impl Node {
+    pub const fn new(address: u8, config: BusConfig) -> Self {
+        Self {
+            address,
+            config,
+            queue: ReadingQueue::new(),
+            next_seq: 0,
+            pending: None,
+            reply: None,
+        }
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Queue a reading for the next poll. A full queue makes room by dropping the oldest
+    /// reading, which is returned.
+    pub fn push(&mut self, reading: SensorReading) -> Option<SensorReading> {
+        self.queue.push(reading)
+    }
+
+    /// Handle a frame received from the bus, `address` as reported by the parser.
+    pub fn on_frame(&mut self, address: u8, frame: &Frame, now_ms: u64) -> Option<NodeEvent> {
+        if address != self.address {
+            return None;
+        }
+
+        match *frame {
+            Frame::Poll { max_readings } => {
+                // Unacknowledged data goes out again before anything new
+                let reply = match &self.pending {
+                    Some(pending) => pending.clone(),
+                    None => self.next_frame(max_readings),
+                };
+                self.reply = Some((reply, now_ms + self.config.turnaround_ms));
+                None
+            }
+            Frame::Ack { seq, .. } => {
+                if self.pending.as_ref()?.seq() != seq {
+                    return None;
+                }
+                self.pending = None;
+                Some(NodeEvent::Delivered { seq })
+            }
+            _ => None,
+        }
+    }
+
+    /// Let time pass: answers a poll once the turnaround is over.
+    pub fn poll(&mut self, now_ms: u64) -> Option<NodeEvent> {
+        if now_ms < self.next_deadline()? {
+            return None;
+        }
+        let (frame, _) = self.reply.take()?;
+        Some(NodeEvent::Transmit(frame))
+    }
+
+    /// When [`Node::poll`] has to be called next, if an answer is waiting.
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.reply.as_ref().map(|(_, not_before)| *not_before)
+    }
+
+    fn next_frame(&mut self, max_readings: u8) -> Frame {
+        if self.queue.is_empty() {
+            return Frame::Idle;
+        }
+
+        let readings = self.queue.take((max_readings as usize).max(1));
+        let frame = Frame::Data {
+            seq: self.next_seq,
+            readings,
+        };
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.pending = Some(frame.clone());
+        frame
+    }
+}
+
+/// Parse a decimal node address at compile time, e.g. from `option_env!("NODE_ADDRESS")`.
+///
+/// Panics (so in a `const` fails the build) on anything but a number from 1 to 255.
+pub const fn parse_address(text: &str) -> u8 {
+    let bytes = text.as_bytes();
+    assert!(!bytes.is_empty(), "empty node address");
+
+    let mut address: u32 = 0;
+    let mut index = 0;
+    while index < bytes.len() {
+        let digit = bytes[index];
+        assert!(
+            digit.is_ascii_digit(),
+            "node address must be a decimal number"
+        );
+        address = address * 10 + (digit - b'0') as u32;
+        assert!(address <= u8::MAX as u32, "node address must be below 256");
+        index += 1;
+    }
+    assert!(
+        address != DIRECT_ADDRESS as u32,
+        "node address 0 is reserved"
+    );
+    address as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_reading, DataFrame, FrameParser, MAX_FRAME_SIZE};
+
+    const MASTER: usize = 0;
+
+    /// Bytes per millisecond at 115200 baud, rounded down
+    const BYTES_PER_MS: u64 = 11;
+
+    struct Transmission {
+        from: usize,
+        end: u64,
+        bytes: heapless::Vec<u8, MAX_FRAME_SIZE>,
+        collided: bool,
+    }
+
+    /// Master and nodes on one shared line. Everybody hears every frame except their own, and
+    /// frames that overlap in time are destroyed.
+    struct SimulatedBus {
+        master: Master,
+        nodes: heapless::Vec<Node, MAX_NODES>,
+        parsers: heapless::Vec<FrameParser, { MAX_NODES + 1 }>,
+        on_air: heapless::Vec<Transmission, { MAX_NODES + 1 }>,
+        /// End of the last transmission that finished, and who sent it
+        quiet_since: u64,
+        last_from: usize,
+        collisions: u32,
+        /// Frames put on the bus so far, lets a test lose specific ones
+        sent: u32,
+        lost: &'static [u32],
+        /// Node neither hearing nor answering, e.g. while it reboots
+        down: Option<usize>,
+        readings: heapless::Vec<(u8, u32), 256>,
+        no_responses: heapless::Vec<(u8, u8), 512>,
+        delivered: heapless::Vec<(u8, u8), 64>,
+    }
+
+    impl SimulatedBus {
+        fn new(present: &[u8], polled: &[u8]) -> Self {
+            let mut master = Master::new(4, BusConfig::default());
+            for &address in polled {
+                master.add_node(address).unwrap();
+            }
+            let nodes = present
+                .iter()
+                .map(|&address| Node::new(address, BusConfig::default()))
+                .collect();
+            let parsers = (0..=present.len()).map(|_| FrameParser::new()).collect();
+
+            Self {
+                master,
+                nodes,
+                parsers,
+                on_air: heapless::Vec::new(),
+                quiet_since: 0,
+                last_from: MASTER,
+                collisions: 0,
+                sent: 0,
+                lost: &[],
+                down: None,
+                readings: heapless::Vec::new(),
+                no_responses: heapless::Vec::new(),
+                delivered: heapless::Vec::new(),
+            }
+        }
+
+        fn run(&mut self, from_ms: u64, to_ms: u64) {
+            for now in from_ms..to_ms {
+                self.deliver(now);
+
+                // Writing a frame takes a while, nobody does anything else meanwhile
+                let event = if self.is_sending(MASTER) {
+                    None
+                } else {
+                    self.master.poll(now)
+                };
+                if let Some(MasterEvent::Transmit { address, frame }) = event {
+                    self.transmit(MASTER, address, &frame, now);
+                } else if let Some(MasterEvent::NoResponse { address, missed }) = event {
+                    self.no_responses.push((address, missed)).unwrap();
+                }
+
+                for index in 0..self.nodes.len() {
+                    if self.is_sending(index + 1) || self.down == Some(index) {
+                        continue;
+                    }
+                    if let Some(NodeEvent::Transmit(frame)) = self.nodes[index].poll(now) {
+                        let address = self.nodes[index].address();
+                        self.transmit(index + 1, address, &frame, now);
+                    }
+                }
+            }
+        }
+
+        fn is_sending(&self, participant: usize) -> bool {
+            self.on_air.iter().any(|t| t.from == participant)
+        }
+
+        fn transmit(&mut self, from: usize, address: u8, frame: &Frame, now: u64) {
+            let mut encoder = DataFrame::new();
+            encoder.set_address(address);
+            encoder.build_frame(frame).unwrap();
+            let bytes = heapless::Vec::from_slice(encoder.get_bytes()).unwrap();
+
+            let sent = self.sent;
+            self.sent += 1;
+            if self.lost.contains(&sent) {
+                return;
+            }
+
+            // Collision safe means nobody talks over someone else or right after them
+            assert!(
+                from == self.last_from
+                    || now >= self.quiet_since + BusConfig::default().turnaround_ms,
+                "{from} sent at {now}, bus quiet only since {}",
+                self.quiet_since
+            );
+            let collided = !self.on_air.is_empty();
+            if collided {
+                self.collisions += 1;
+                for transmission in &mut self.on_air {
+                    transmission.collided = true;
+                }
+            }
+            self.on_air
+                .push(Transmission {
+                    from,
+                    end: now + 1 + bytes.len() as u64 / BYTES_PER_MS,
+                    bytes,
+                    collided,
+                })
+                .ok()
+                .unwrap();
+        }
+
+        fn deliver(&mut self, now: u64) {
+            while let Some(index) = self.on_air.iter().position(|t| t.end <= now) {
+                let transmission = self.on_air.swap_remove(index);
+                self.quiet_since = self.quiet_since.max(transmission.end);
+                self.last_from = transmission.from;
+                if transmission.collided {
+                    continue;
+                }
+
+                for listener in 0..self.parsers.len() {
+                    if listener == transmission.from
+                        || self.down.is_some_and(|node| listener == node + 1)
+                    {
+                        continue;
+                    }
+                    let parser = &mut self.parsers[listener];
+                    let Some(frame) = transmission
+                        .bytes
+                        .iter()
+                        .find_map(|&byte| parser.process_byte(byte).unwrap())
+                    else {
+                        continue;
+                    };
+                    let address = parser.address();
+
+                    if listener == MASTER {
+                        if let Some(MasterEvent::Readings { address, readings }) =
+                            self.master.on_frame(address, &frame, now)
+                        {
+                            for reading in &readings {
+                                self.readings.push((address, reading.timestamp)).unwrap();
+                            }
+                        }
+                    } else {
+                        let node = &mut self.nodes[listener - 1];
+                        if let Some(NodeEvent::Delivered { seq }) =
+                            node.on_frame(address, &frame, now)
+                        {
+                            self.delivered.push((node.address(), seq)).unwrap();
+                        }
+                    }
+                }
+            }
+        }
+
+        /// The node comes back with nothing queued and counting from zero
+        fn restart(&mut self, node: usize) {
+            self.nodes[node] = Node::new(self.nodes[node].address(), BusConfig::default());
+            self.parsers[node + 1] = FrameParser::new();
+        }
+
+        fn measure(&mut self, node: usize, timestamp: u32) {
+            let dropped = self.nodes[node].push(test_reading(timestamp));
+            assert_eq!(dropped, None);
+        }
+
+        fn readings_from(&self, address: u8) -> heapless::Vec<u32, 64> {
+            self.readings
+                .iter()
+                .filter(|(from, _)| *from == address)
+                .map(|(_, timestamp)| *timestamp)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn polls_every_node_without_collisions() {
+        // Node 9 is configured on the master but not connected
+        let mut bus = SimulatedBus::new(&[1, 2, 3], &[1, 2, 3, 9]);
+
+        for second in 0..5 {
+            for node in 0..3 {
+                for reading in 0..3 {
+                    bus.measure(node, second * 3 + reading);
+                }
+            }
+            bus.run(second as u64 * 1000, (second as u64 + 1) * 1000);
+        }
+
+        assert_eq!(bus.collisions, 0);
+        let expected: heapless::Vec<u32, 64> = (0..15).collect();
+        for address in [1, 2, 3] {
+            assert_eq!(bus.readings_from(address), expected, "node {address}");
+        }
+        assert!(!bus.no_responses.is_empty());
+        assert!(bus.no_responses.iter().all(|(address, _)| *address == 9));
+        let missed: heapless::Vec<u8, 512> = bus.no_responses.iter().map(|(_, m)| *m).collect();
+        assert!(missed
+            .windows(2)
+            .all(|pair| pair[1] == pair[0].saturating_add(1)));
+    }
+
+    #[test]
+    fn lost_ack_is_not_delivered_twice() {
+        let mut bus = SimulatedBus::new(&[5], &[5]);
+        bus.measure(0, 1);
+        // Frames: poll, data, ack - lose the ack
+        bus.lost = &[2];
+
+        bus.run(0, 200);
+        assert_eq!(bus.readings_from(5).as_slice(), &[1]);
+        // Delivered on the second attempt, nothing new since then
+        assert_eq!(bus.delivered.as_slice(), &[(5, 0)]);
+        assert_eq!(bus.collisions, 0);
+
+        bus.measure(0, 2);
+        bus.run(200, 400);
+        assert_eq!(bus.readings_from(5).as_slice(), &[1, 2]);
+        assert_eq!(bus.delivered.as_slice(), &[(5, 0), (5, 1)]);
+    }
+
+    #[test]
+    fn restarted_node_is_not_taken_for_a_duplicate() {
+        let mut bus = SimulatedBus::new(&[5], &[5]);
+        bus.measure(0, 1);
+        bus.run(0, 200);
+        assert_eq!(bus.delivered.as_slice(), &[(5, 0)]);
+
+        // Reboots, then sends sequence number 0 again
+        bus.down = Some(0);
+        bus.run(200, 500);
+        bus.down = None;
+        bus.restart(0);
+        bus.measure(0, 2);
+        bus.run(500, 700);
+
+        assert_eq!(bus.readings_from(5).as_slice(), &[1, 2]);
+        assert_eq!(bus.delivered.as_slice(), &[(5, 0), (5, 0)]);
+        assert_eq!(bus.collisions, 0);
+    }
+
+    #[test]
+    fn batches_follow_the_master() {
+        let mut node = Node::new(3, BusConfig::default());
+        for timestamp in 0..10 {
+            node.push(test_reading(timestamp));
+        }
+
+        // Polls for somebody else are none of our business
+        assert_eq!(node.on_frame(4, &Frame::Poll { max_readings: 4 }, 0), None);
+        assert_eq!(node.next_deadline(), None);
+
+        node.on_frame(3, &Frame::Poll { max_readings: 4 }, 10);
+        assert_eq!(node.poll(10), None);
+        let Some(NodeEvent::Transmit(Frame::Data { seq: 0, readings })) = node.poll(11) else {
+            panic!("expected a data frame");
+        };
+        assert_eq!(readings.len(), 4);
+        assert_eq!(
+            node.on_frame(
+                3,
+                &Frame::Ack {
+                    seq: 0,
+                    max_readings: 4
+                },
+                12
+            ),
+            Some(NodeEvent::Delivered { seq: 0 })
+        );
+    }
+
+    #[test]
+    fn full_queue_drops_oldest() {
+        let mut node = Node::new(3, BusConfig::default());
+        for timestamp in 0..NODE_QUEUE_LENGTH as u32 {
+            assert_eq!(node.push(test_reading(timestamp)), None);
+        }
+        assert_eq!(node.push(test_reading(100)), Some(test_reading(0)));
+    }
+
+    #[test]
+    fn node_addresses() {
+        let mut master = Master::new(4, BusConfig::default());
+        assert_eq!(
+            master.add_node(DIRECT_ADDRESS),
+            Err(BusError::InvalidAddress(0))
+        );
+        for address in 1..=MAX_NODES as u8 {
+            master.add_node(address).unwrap();
+        }
+        assert_eq!(master.add_node(1), Err(BusError::DuplicateAddress(1)));
+        assert_eq!(master.add_node(100), Err(BusError::TooManyNodes));
+
+        const ADDRESS: u8 = parse_address("17");
+        assert_eq!(ADDRESS, 17);
+        assert_eq!(parse_address("255"), 255);
+    }
+
+    #[test]
+    #[should_panic(expected = "below 256")]
+    fn address_out_of_range() {
+        parse_address("256");
+    }
+}
@@ -0,0 +1,252 @@
+use heapless::Vec;
+
+use crate::{
+    crc16, FrameError, SensorReading, DIRECT_ADDRESS, END_BYTE, ESCAPE_BYTE, ESCAPE_XOR,
+    MAX_FRAME_SIZE, MAX_PAYLOAD_LENGTH, MAX_READINGS_PER_FRAME, PROBE_PATTERN, PROTOCOL_VERSION,
+    START_BYTE,
+};
+#[cfg(feature = "auth")]
+use crate::{Key, AUTHENTICATED};
+
+/// What a frame is for, sent right after the address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    /// Carries one or more [`SensorReading`]s
+    Data = 0x01,
+    /// The data frame with this sequence number arrived
+    Ack = 0x02,
+    /// A frame with this sequence number arrived broken, please send it again
+    Nack = 0x03,
+    /// The bus master asks a node for its data, see [`crate::bus`]
+    Poll = 0x04,
+    /// A polled node has nothing to report
+    Idle = 0x05,
+    /// Switch to another baud rate, or agree to, see [`crate::baud`]
+    Baud = 0x06,
+    /// Test frame while trying a baud rate, echoed by the other side
+    Probe = 0x07,
+}
+
+impl TryFrom<u8> for FrameKind {
+    type Error = FrameError;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        match byte {
+            0x01 => Ok(Self::Data),
+            0x02 => Ok(Self::Ack),
+            0x03 => Ok(Self::Nack),
+            0x04 => Ok(Self::Poll),
+            0x05 => Ok(Self::Idle),
+            0x06 => Ok(Self::Baud),
+            0x07 => Ok(Self::Probe),
+            _ => Err(FrameError::UnknownKind(byte)),
+        }
+    }
+}
+
+/// The readings of one data frame
+pub type Readings = Vec<SensorReading, MAX_READINGS_PER_FRAME>;
+
+/// A decoded frame
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    Data {
+        seq: u8,
+        readings: Readings,
+    },
+    /// `max_readings` is how many readings per frame the receiver accepts
+    Ack {
+        seq: u8,
+        max_readings: u8,
+    },
+    Nack {
+        seq: u8,
+    },
+    /// `max_readings` is how many readings per frame the master accepts
+    Poll {
+        max_readings: u8,
+    },
+    Idle,
+    Baud {
+        rate: u32,
+    },
+    /// Carries [`PROBE_PATTERN`], which is made to trip up a struggling line
+    Probe {
+        seq: u8,
+    },
+}
+
+impl Frame {
+    pub fn kind(&self) -> FrameKind {
+        match self {
+            Self::Data { .. } => FrameKind::Data,
+            Self::Ack { .. } => FrameKind::Ack,
+            Self::Nack { .. } => FrameKind::Nack,
+            Self::Poll { .. } => FrameKind::Poll,
+            Self::Idle => FrameKind::Idle,
+            Self::Baud { .. } => FrameKind::Baud,
+            Self::Probe { .. } => FrameKind::Probe,
+        }
+    }
+
+    /// Sequence number of the data frame this frame is or refers to, or of the probe. 0 for the
+    /// other frames which don't have one
+    pub fn seq(&self) -> u8 {
+        match *self {
+            Self::Data { seq, .. }
+            | Self::Ack { seq, .. }
+            | Self::Nack { seq }
+            | Self::Probe { seq } => seq,
+            Self::Poll { .. } | Self::Idle | Self::Baud { .. } => 0,
+        }
+    }
+}
+
+/// Encoder for outgoing frames, reusing its buffer from frame to frame
+pub struct DataFrame {
+    data: Vec<u8, MAX_FRAME_SIZE>,
+    address: u8,
+    #[cfg(feature = "auth")]
+    key: Option<Key>,
+    /// Counter of the last authenticated frame
+    counter: u32,
+}
+
+impl DataFrame {
+    /// An encoder for the point-to-point link, see [`DIRECT_ADDRESS`].
+    pub fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            address: DIRECT_ADDRESS,
+            #[cfg(feature = "auth")]
+            key: None,
+            counter: 0,
+        }
+    }
+
+    /// An encoder adding a tag and a counter to every frame, see [`crate::auth`].
+    #[cfg(feature = "auth")]
+    pub fn authenticated(key: Key) -> Self {
+        Self {
+            key: Some(key),
+            ..Self::new()
+        }
+    }
+
+    /// Counter of the last authenticated frame built, 0 before the first.
+    pub fn counter(&self) -> u32 {
+        self.counter
+    }
+
+    /// Count on from `seen`, the counter of a frame received from the other side (see
+    /// [`crate::FrameParser::last_counter`]), if it is ahead of ours.
+    pub fn sync_counter(&mut self, seen: Option<u32>) {
+        self.counter = self.counter.max(seen.unwrap_or(0));
+    }
+
+    /// Address the following frames to (or, for a bus node, send them from) `address`.
+    pub fn set_address(&mut self, address: u8) {
+        self.address = address;
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    pub fn build_frame(&mut self, frame: &Frame) -> Result<(), FrameError> {
+        self.data.clear();
+
+        let mut payload: Vec<u8, MAX_PAYLOAD_LENGTH> = Vec::new();
+        match frame {
+            Frame::Data { readings, .. } => {
+                for reading in readings {
+                    // Can't overflow, the payload has room for the most readings there can be
+                    _ = payload.extend_from_slice(&reading.to_bytes());
+                }
+            }
+            Frame::Ack { max_readings, .. } | Frame::Poll { max_readings } => {
+                _ = payload.push(*max_readings)
+            }
+            Frame::Nack { .. } | Frame::Idle => {}
+            Frame::Baud { rate } => _ = payload.extend_from_slice(&rate.to_be_bytes()),
+            Frame::Probe { .. } => _ = payload.extend_from_slice(&PROBE_PATTERN),
+        }
+
+        // Version, address, kind, sequence number, length, then the payload
+        let header = [
+            PROTOCOL_VERSION,
+            self.address,
+            self.kind_byte(frame),
+            frame.seq(),
+            payload.len() as u8,
+        ];
+        let mut body: Vec<u8, MAX_FRAME_SIZE> = Vec::new();
+        body.extend_from_slice(&header)
+            .and_then(|_| body.extend_from_slice(&payload))
+            .map_err(|_| FrameError::BufferOverflow)?;
+        #[cfg(feature = "auth")]
+        self.authenticate(&mut body)?;
+
+        // CRC over the unescaped body
+        let crc = crc16(&body);
+
+        self.push(START_BYTE)?;
+        for byte in body.into_iter().chain(crc.to_be_bytes()) {
+            self.push_escaped(byte)?;
+        }
+        self.push(END_BYTE)?;
+
+        Ok(())
+    }
+
+    pub fn get_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    #[cfg(feature = "auth")]
+    fn kind_byte(&self, frame: &Frame) -> u8 {
+        match self.key {
+            Some(_) => frame.kind() as u8 | AUTHENTICATED,
+            None => frame.kind() as u8,
+        }
+    }
+
+    #[cfg(not(feature = "auth"))]
+    fn kind_byte(&self, frame: &Frame) -> u8 {
+        frame.kind() as u8
+    }
+
+    /// Append the next counter and the tag over everything before it.
+    #[cfg(feature = "auth")]
+    fn authenticate(&mut self, body: &mut Vec<u8, MAX_FRAME_SIZE>) -> Result<(), FrameError> {
+        let Some(key) = self.key else {
+            return Ok(());
+        };
+        self.counter = self.counter.wrapping_add(1);
+        body.extend_from_slice(&self.counter.to_be_bytes())
+            .map_err(|_| FrameError::BufferOverflow)?;
+        let tag = key.tag(&[body]);
+        body.extend_from_slice(&tag)
+            .map_err(|_| FrameError::BufferOverflow)
+    }
+
+    fn push(&mut self, byte: u8) -> Result<(), FrameError> {
+        self.data.push(byte).map_err(|_| FrameError::BufferOverflow)
+    }
+
+    /// Push a byte between start and end byte, escaping it if it looks like framing.
+    fn push_escaped(&mut self, byte: u8) -> Result<(), FrameError> {
+        if matches!(byte, START_BYTE | END_BYTE | ESCAPE_BYTE) {
+            self.push(ESCAPE_BYTE)?;
+            self.push(byte ^ ESCAPE_XOR)
+        } else {
+            self.push(byte)
+        }
+    }
+}
+
+impl Default for DataFrame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,522 @@
+//! The frame format spoken between board1 (sensor board) and board2 (display board).
+//!
+//! Frame layout:
+//!
+//! ```text
+//! [START_BYTE][version][address][kind][seq][length][payload ...][crc: u16 BE][END_BYTE]
+//! ```
+//!
+//! `address` is [`DIRECT_ADDRESS`] on the point-to-point link between the two boards. On a
+//! multi-drop bus it is the node the master talks to, or the node answering (see [`bus`]).
+//!
+//! `kind` tells data frames from acknowledgements (see [`FrameKind`]), and `seq` is the sequence
+//! number of the data frame, repeated in its [`FrameKind::Ack`] or [`FrameKind::Nack`].
+//!
+//! Data frames carry 1 to [`MAX_READINGS_PER_FRAME`] readings of
+//! `[sensor_id][value: f32 LE][timestamp: u32 LE]` each. An ack carries one byte, the number of
+//! readings per frame the receiver accepts, so the sender only batches as much as the receiver
+//! can take. A nack carries nothing. Polls carry the same byte as an ack, idle answers nothing.
+//! Baud frames carry a baud rate as `u32 BE`, probes the [`PROBE_PATTERN`].
+//!
+//! With the `auth` feature, frames can carry `[counter: u32 BE][tag]` right after the payload,
+//! marked by [`AUTHENTICATED`] in `kind` and not counted in `length` (see `src/auth.rs`).
+//!
+//! `crc` is the CRC-16/CCITT-FALSE of everything between `START_BYTE` and the CRC itself.
+//! Frames with a different `version` are rejected as a whole, so boards running different
+//! firmware don't misread each other.
+//!
+//! `START_BYTE`, `END_BYTE` and `ESCAPE_BYTE` never show up between the start and the end of a
+//! frame: they are sent as `ESCAPE_BYTE` followed by the byte XOR `ESCAPE_XOR`. So a start byte
+//! on the wire is always the start of a frame, and the receiver can resynchronize after noise
+//! or lost bytes. Length and CRC are calculated over the unescaped bytes.
+//!
+//! Everything in here is hardware independent, so both boards share it and it can be tested on
+//! the host with `cargo test`.
+
+#![no_std]
+
+#[cfg(feature = "auth")]
+pub mod auth;
+pub mod baud;
+pub mod bus;
+mod crc;
+mod frame;
+mod link;
+mod parser;
+pub mod schedule;
+mod sensor;
+mod statistics;
+
+#[cfg(feature = "auth")]
+pub use auth::Key;
+pub use crc::crc16;
+pub use frame::{DataFrame, Frame, FrameKind, Readings};
+pub use link::{Received, Receiver, RetransmitConfig, SendError, Sender, SenderEvent};
+pub use parser::{FrameError, FrameParser, ParseState};
+pub use sensor::{
+    sensor_index, sensor_info, SensorInfo, SENSORS, SENSOR_HUMIDITY, SENSOR_LIGHT, SENSOR_PRESSURE,
+    SENSOR_TEMPERATURE, SENSOR_VOLTAGE,
+};
+pub use statistics::{SensorStatistics, Statistics, Summary};
+
+/// Version of the frame format, bumped on every incompatible change.
+///
+/// Starts at 0x10 to stay clear of the sensor IDs, which the unversioned format of the first
+/// firmware sent in this place.
+pub const PROTOCOL_VERSION: u8 = 0x14;
+
+// Protocol constants
+pub const START_BYTE: u8 = 0xAA;
+pub const END_BYTE: u8 = 0x55;
+pub const ESCAPE_BYTE: u8 = 0x7D;
+pub const ESCAPE_XOR: u8 = 0x20;
+
+/// Address of every frame on the point-to-point link, never used by a bus node
+pub const DIRECT_ADDRESS: u8 = 0x00;
+
+/// Length of one [`SensorReading`] in the payload of a data frame
+pub const READING_LENGTH: usize = 9;
+
+/// Most readings a data frame can carry
+pub const MAX_READINGS_PER_FRAME: usize = 8;
+
+/// Longest payload any frame kind carries
+pub const MAX_PAYLOAD_LENGTH: usize = MAX_READINGS_PER_FRAME * READING_LENGTH;
+
+/// Payload of a [`Frame::Probe`]: all bits set and cleared in turn, runs of ones and zeros,
+/// and the framing bytes which need escaping
+pub const PROBE_PATTERN: [u8; 16] = [
+    0x00, 0xFF, 0x55, 0xAA, 0x0F, 0xF0, 0x33, 0xCC, 0x01, 0x80, 0x7E, 0x81, 0x7D, 0x5A, 0xA5, 0xFE,
+];
+
+/// Set in the `kind` of authenticated frames
+pub const AUTHENTICATED: u8 = 0x80;
+
+/// Length of the truncated tag of an authenticated frame
+pub const TAG_LENGTH: usize = 4;
+
+/// What authentication adds to a frame: the counter and the tag
+pub const AUTH_LENGTH: usize = 4 + TAG_LENGTH;
+
+/// Maximum frame size on the wire: start and end byte, and in between header, payload,
+/// authentication and CRC with every byte escaped in the worst case
+pub const MAX_FRAME_SIZE: usize = 2 + 2 * (5 + MAX_PAYLOAD_LENGTH + AUTH_LENGTH + 2);
+
+/// Pause between two bytes of the same frame allowed on top of the time the UART takes to hand
+/// them over, see [`baud::inter_byte_timeout_ms`]. Senders write a frame in one go, so after
+/// that long without a byte the rest of the frame is lost (see [`FrameParser::on_timeout`]).
+pub const INTER_BYTE_TIMEOUT_MS: u64 = 5;
+
+/// One measurement of one sensor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SensorReading {
+    pub sensor_id: u8,
+    pub value: f32,
+    pub timestamp: u32,
+}
+
+impl SensorReading {
+    /// Wire format: `[sensor_id][value: f32 LE][timestamp: u32 LE]`
+    pub fn to_bytes(&self) -> [u8; READING_LENGTH] {
+        let mut bytes = [0u8; READING_LENGTH];
+        bytes[0] = self.sensor_id;
+        bytes[1..5].copy_from_slice(&self.value.to_le_bytes());
+        bytes[5..9].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; READING_LENGTH]) -> Self {
+        let mut value = [0u8; 4];
+        value.copy_from_slice(&bytes[1..5]);
+        let mut timestamp = [0u8; 4];
+        timestamp.copy_from_slice(&bytes[5..9]);
+
+        Self {
+            sensor_id: bytes[0],
+            value: f32::from_le_bytes(value),
+            timestamp: u32::from_le_bytes(timestamp),
+        }
+    }
+}
+
+/// The reading the tests send around, only the timestamp tells them apart.
+#[cfg(test)]
+pub(crate) fn test_reading(timestamp: u32) -> SensorReading {
+    SensorReading {
+        sensor_id: SENSOR_HUMIDITY,
+        value: 45.0,
+        timestamp,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(reading: SensorReading) -> Frame {
+        Frame::Data {
+            seq: reading.timestamp as u8,
+            readings: Readings::from_slice(&[reading]).unwrap(),
+        }
+    }
+
+    fn round_trip(frame: &Frame) -> Option<Frame> {
+        let mut encoder = DataFrame::new();
+        encoder.build_frame(frame).unwrap();
+
+        let mut parser = FrameParser::new();
+        let mut result = None;
+        for &byte in encoder.get_bytes() {
+            if let Some(frame) = parser.process_byte(byte).unwrap() {
+                assert!(result.is_none(), "more than one frame from one frame");
+                result = Some(frame);
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn reading_survives_round_trip() {
+        let reading = SensorReading {
+            sensor_id: SENSOR_HUMIDITY,
+            value: 45.7,
+            timestamp: 1234,
+        };
+        assert_eq!(round_trip(&data(reading)), Some(data(reading)));
+    }
+
+    #[test]
+    fn acknowledgements_survive_round_trip() {
+        let frames = [
+            Frame::Ack {
+                seq: 0,
+                max_readings: MAX_READINGS_PER_FRAME as u8,
+            },
+            Frame::Nack { seq: START_BYTE },
+        ];
+        for frame in frames {
+            assert_eq!(round_trip(&frame), Some(frame));
+        }
+    }
+
+    #[test]
+    fn baud_frames_survive_round_trip() {
+        for frame in [
+            Frame::Baud { rate: 921_600 },
+            Frame::Baud { rate: u32::MAX },
+            Frame::Probe { seq: START_BYTE },
+        ] {
+            assert_eq!(round_trip(&frame), Some(frame));
+        }
+    }
+
+    #[test]
+    fn bus_frames_survive_round_trip_with_address() {
+        let mut encoder = DataFrame::new();
+        let mut parser = FrameParser::new();
+        let frames = [
+            (0x07, Frame::Poll { max_readings: 3 }),
+            (END_BYTE, Frame::Idle),
+        ];
+
+        for (address, frame) in frames {
+            encoder.set_address(address);
+            encoder.build_frame(&frame).unwrap();
+            let parsed = encoder
+                .get_bytes()
+                .iter()
+                .find_map(|&byte| parser.process_byte(byte).unwrap());
+            assert_eq!(parsed, Some(frame));
+            assert_eq!(parser.address(), address);
+        }
+    }
+
+    #[test]
+    fn extreme_values_survive_round_trip() {
+        for (value, timestamp) in [(0.0, 0), (-1.5e-9, 1), (f32::MAX, u32::MAX)] {
+            let reading = SensorReading {
+                sensor_id: 0xFF,
+                value,
+                timestamp,
+            };
+            assert_eq!(round_trip(&data(reading)), Some(data(reading)));
+        }
+    }
+
+    #[test]
+    fn consecutive_frames_are_all_parsed() {
+        let mut frame = DataFrame::new();
+        let mut parser = FrameParser::new();
+        let mut received = 0;
+
+        for timestamp in 0..10 {
+            let reading = SensorReading {
+                sensor_id: SENSOR_HUMIDITY,
+                value: timestamp as f32,
+                timestamp,
+            };
+            frame.build_frame(&data(reading)).unwrap();
+            for &byte in frame.get_bytes() {
+                if let Some(parsed) = parser.process_byte(byte).unwrap() {
+                    assert_eq!(parsed, data(reading));
+                    received += 1;
+                }
+            }
+        }
+        assert_eq!(received, 10);
+    }
+
+    #[test]
+    fn corrupted_byte_fails_crc() {
+        let mut frame = DataFrame::new();
+        frame.build_frame(&data(test_reading(7))).unwrap();
+        let mut bytes: heapless::Vec<u8, MAX_FRAME_SIZE> =
+            heapless::Vec::from_slice(frame.get_bytes()).unwrap();
+        bytes[6] ^= 0x01;
+
+        let mut parser = FrameParser::new();
+        let errors: usize = bytes
+            .iter()
+            .filter(|&&byte| {
+                matches!(
+                    parser.process_byte(byte),
+                    Err(FrameError::CrcMismatch { .. })
+                )
+            })
+            .count();
+        assert_eq!(errors, 1);
+    }
+
+    #[test]
+    fn framing_bytes_in_payload_are_escaped() {
+        let reading = SensorReading {
+            sensor_id: START_BYTE,
+            value: f32::from_le_bytes([END_BYTE, ESCAPE_BYTE, START_BYTE, 0x00]),
+            timestamp: u32::from_le_bytes([START_BYTE, END_BYTE, ESCAPE_BYTE, START_BYTE]),
+        };
+
+        let mut frame = DataFrame::new();
+        frame.build_frame(&data(reading)).unwrap();
+        let bytes = frame.get_bytes();
+        let inner = &bytes[1..bytes.len() - 1];
+        assert!(!inner.contains(&START_BYTE));
+        assert!(!inner.contains(&END_BYTE));
+
+        assert_eq!(round_trip(&data(reading)), Some(data(reading)));
+    }
+
+    /// Tiny deterministic pseudo random generator, good enough to make noise
+    struct Lcg(u32);
+
+    impl Lcg {
+        fn next(&mut self) -> u8 {
+            self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (self.0 >> 24) as u8
+        }
+    }
+
+    #[test]
+    fn resynchronizes_after_noise_and_truncation() {
+        let mut rng = Lcg(42);
+        let mut frame = DataFrame::new();
+        let mut parser = FrameParser::new();
+        let mut received: heapless::Vec<u32, 200> = heapless::Vec::new();
+        let mut expected: heapless::Vec<u32, 200> = heapless::Vec::new();
+
+        for timestamp in 0..200u32 {
+            let reading = SensorReading {
+                sensor_id: SENSOR_HUMIDITY,
+                value: timestamp as f32 * 0.5,
+                timestamp,
+            };
+            frame.build_frame(&data(reading)).unwrap();
+            let bytes = frame.get_bytes();
+
+            // Every third frame gets cut off somewhere, every fourth is followed by line noise
+            let sent = if timestamp % 3 == 0 {
+                &bytes[..1 + rng.next() as usize % (bytes.len() - 2)]
+            } else {
+                expected.push(timestamp).unwrap();
+                bytes
+            };
+            let noise_length = if timestamp % 4 == 0 {
+                rng.next() % 16
+            } else {
+                0
+            };
+            let noise: heapless::Vec<u8, 16> = (0..noise_length).map(|_| rng.next()).collect();
+
+            for &byte in sent.iter().chain(&noise) {
+                if let Ok(Some(parsed)) = parser.process_byte(byte) {
+                    assert_eq!(parsed, data(reading));
+                    received.push(timestamp).unwrap();
+                }
+            }
+        }
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn dropped_byte_does_not_swallow_next_frame() {
+        let mut frame = DataFrame::new();
+        let mut parser = FrameParser::new();
+
+        let first = SensorReading {
+            sensor_id: SENSOR_HUMIDITY,
+            value: 1.0,
+            timestamp: 1,
+        };
+        frame.build_frame(&data(first)).unwrap();
+        // Lose the end byte of the first frame
+        let bytes = frame.get_bytes();
+        for &byte in &bytes[..bytes.len() - 1] {
+            assert_eq!(parser.process_byte(byte), Ok(None));
+        }
+
+        let second = SensorReading {
+            timestamp: 2,
+            ..first
+        };
+        frame.build_frame(&data(second)).unwrap();
+        let mut results = frame
+            .get_bytes()
+            .iter()
+            .map(|&byte| parser.process_byte(byte));
+        assert_eq!(results.next(), Some(Err(FrameError::Truncated)));
+        assert_eq!(results.last(), Some(Ok(Some(data(second)))));
+    }
+
+    #[test]
+    fn timeout_drops_partial_frame() {
+        let mut frame = DataFrame::new();
+        let mut parser = FrameParser::new();
+        assert_eq!(parser.on_timeout(), None);
+
+        let reading = test_reading(3);
+        frame.build_frame(&data(reading)).unwrap();
+        let bytes = frame.get_bytes();
+        for &byte in &bytes[..6] {
+            assert_eq!(parser.process_byte(byte), Ok(None));
+        }
+        assert_eq!(parser.on_timeout(), Some(FrameError::Truncated));
+        assert_eq!(parser.state(), &ParseState::WaitingForStart);
+
+        // The tail of the lost frame is ignored, the next one parses fine
+        for &byte in &bytes[6..] {
+            assert_eq!(parser.process_byte(byte), Ok(None));
+        }
+        let last = bytes.iter().map(|&byte| parser.process_byte(byte)).last();
+        assert_eq!(last, Some(Ok(Some(data(reading)))));
+    }
+
+    #[test]
+    fn stray_end_byte_aborts_frame() {
+        let mut parser = FrameParser::new();
+
+        assert_eq!(parser.process_byte(START_BYTE), Ok(None));
+        assert_eq!(parser.process_byte(PROTOCOL_VERSION), Ok(None));
+        assert_eq!(parser.process_byte(DIRECT_ADDRESS), Ok(None));
+        assert_eq!(parser.process_byte(FrameKind::Ack as u8), Ok(None));
+        assert_eq!(parser.process_byte(END_BYTE), Err(FrameError::Truncated));
+        assert_eq!(parser.state(), &ParseState::WaitingForStart);
+    }
+
+    #[test]
+    fn other_protocol_version_is_rejected() {
+        let mut frame = DataFrame::new();
+        frame.build_frame(&data(test_reading(7))).unwrap();
+        let mut bytes: heapless::Vec<u8, MAX_FRAME_SIZE> =
+            heapless::Vec::from_slice(frame.get_bytes()).unwrap();
+        bytes[1] = PROTOCOL_VERSION + 1;
+
+        let mut parser = FrameParser::new();
+        let results: heapless::Vec<_, MAX_FRAME_SIZE> = bytes
+            .iter()
+            .map(|&byte| parser.process_byte(byte))
+            .collect();
+        assert_eq!(
+            results[1],
+            Err(FrameError::UnsupportedVersion(PROTOCOL_VERSION + 1))
+        );
+        assert!(results.iter().all(|result| !matches!(result, Ok(Some(_)))));
+    }
+
+    #[test]
+    fn unversioned_frame_is_rejected() {
+        // A humidity frame of the original XOR checksum format
+        let old = [
+            0xAA, 0x02, 0x09, 0x02, 0x00, 0x00, 0x34, 0x42, 0x07, 0x00, 0x00, 0x00,
+        ];
+        let checksum = old[1..].iter().fold(0, |checksum, byte| checksum ^ byte);
+
+        let mut parser = FrameParser::new();
+        let results: heapless::Vec<_, MAX_FRAME_SIZE> = old
+            .iter()
+            .chain(&[checksum, END_BYTE])
+            .map(|&byte| parser.process_byte(byte))
+            .collect();
+        assert_eq!(results[1], Err(FrameError::UnsupportedVersion(0x02)));
+        assert!(results.iter().all(|result| !matches!(result, Ok(Some(_)))));
+    }
+
+    #[test]
+    fn full_batch_of_worst_case_bytes_fits() {
+        // Every single byte needs escaping
+        let reading = SensorReading {
+            sensor_id: START_BYTE,
+            value: f32::from_le_bytes([END_BYTE; 4]),
+            timestamp: u32::from_le_bytes([ESCAPE_BYTE; 4]),
+        };
+        let frame = Frame::Data {
+            seq: START_BYTE,
+            readings: Readings::from_slice(&[reading; MAX_READINGS_PER_FRAME]).unwrap(),
+        };
+        assert_eq!(round_trip(&frame), Some(frame));
+    }
+
+    #[test]
+    fn payload_length_boundaries() {
+        let cases = [
+            (FrameKind::Data, 0, false),
+            (FrameKind::Data, READING_LENGTH, true),
+            (FrameKind::Data, READING_LENGTH + 1, false),
+            (FrameKind::Data, MAX_PAYLOAD_LENGTH - 1, false),
+            (FrameKind::Data, MAX_PAYLOAD_LENGTH, true),
+            (FrameKind::Data, MAX_PAYLOAD_LENGTH + READING_LENGTH, false),
+            (FrameKind::Ack, 0, false),
+            (FrameKind::Ack, 1, true),
+            (FrameKind::Nack, 0, true),
+            (FrameKind::Nack, 1, false),
+            (FrameKind::Poll, 0, false),
+            (FrameKind::Poll, 1, true),
+            (FrameKind::Idle, 0, true),
+            (FrameKind::Idle, 1, false),
+            (FrameKind::Baud, 4, true),
+            (FrameKind::Baud, 1, false),
+            (FrameKind::Probe, PROBE_PATTERN.len(), true),
+            (FrameKind::Probe, 0, false),
+        ];
+
+        for (kind, length, valid) in cases {
+            let mut parser = FrameParser::new();
+            let header = [
+                START_BYTE,
+                PROTOCOL_VERSION,
+                DIRECT_ADDRESS,
+                kind as u8,
+                0,
+                length as u8,
+            ];
+            let results: heapless::Vec<_, 6> = header
+                .iter()
+                .map(|&byte| parser.process_byte(byte))
+                .collect();
+            let expected = if valid {
+                Ok(None)
+            } else {
+                Err(FrameError::InvalidLength(length as u8))
+            };
+            assert_eq!(results[5], expected, "{:?} with length {}", kind, length);
+        }
+    }
+}
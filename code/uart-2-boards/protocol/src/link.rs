@@ -0,0 +1,422 @@
+//! Reliable delivery on top of the frames: stop-and-wait with acknowledgements.
+//!
+//! The [`Sender`] (board1) keeps one data frame in flight until the [`Receiver`] (board2)
+//! acknowledges it, and sends it again when the acknowledgement doesn't arrive in time or the
+//! receiver reports it broken. The receiver acknowledges duplicates again without delivering
+//! them twice, since a lost acknowledgement looks exactly like a lost data frame to the sender.
+//!
+//! A sender that restarted counts from 0 again, which could be the receiver's last sequence
+//! number. Every sender negotiates the baud rate before its first data frame, so a
+//! [`Frame::Baud`] makes the receiver forget the last one. That also lets a retransmission
+//! across a renegotiation through as new, but the sender only steps the rate down after a
+//! series of failures, when the frame most likely never arrived. Keepalives, baud frames too,
+//! only go out after a while without data frames, when none is waiting for its
+//! acknowledgement anymore.
+//!
+//! Like the rest of the crate this never touches the UART or a timer: frames and timestamps in
+//! milliseconds go in, frames to transmit and events come out.
+
+use crate::{
+    baud::transfer_time_ms, Frame, FrameError, Readings, SensorReading, MAX_FRAME_SIZE,
+    MAX_READINGS_PER_FRAME,
+};
+
+/// Timing of the retransmissions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetransmitConfig {
+    /// How long to wait for the acknowledgement before sending again.
+    pub ack_timeout_ms: u64,
+    /// How often a frame is sent at most, including the first time.
+    pub max_attempts: u8,
+}
+
+impl RetransmitConfig {
+    pub const fn new() -> Self {
+        Self {
+            // A frame takes less than 3 ms at 115200 baud, this leaves plenty of slack
+            ack_timeout_ms: 50,
+            max_attempts: 4,
+        }
+    }
+
+    /// Timing for a link at `baud_rate`: time for the largest possible frame each way, and
+    /// 20 ms to spare.
+    pub const fn for_baud_rate(baud_rate: u32) -> Self {
+        Self {
+            ack_timeout_ms: 2 * transfer_time_ms(MAX_FRAME_SIZE, baud_rate) + 20,
+            ..Self::new()
+        }
+    }
+}
+
+impl Default for RetransmitConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What the [`Sender`] wants the caller to know or do.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SenderEvent {
+    /// Put this frame on the wire (again).
+    Transmit(Frame),
+    /// The frame was acknowledged after `attempts` transmissions.
+    Delivered { seq: u8, attempts: u8 },
+    /// No acknowledgement after [`RetransmitConfig::max_attempts`], the frame is dropped.
+    GaveUp { seq: u8 },
+}
+
+/// Why [`Sender::send`] didn't take the readings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendError {
+    /// The previous frame is still waiting for its acknowledgement.
+    Busy,
+    /// Nothing to send, or more than [`Sender::batch_size`] readings.
+    InvalidBatch,
+}
+
+#[derive(Clone, Debug)]
+struct Pending {
+    frame: Frame,
+    attempts: u8,
+    deadline: u64,
+}
+
+/// Sending side of the stop-and-wait protocol.
+pub struct Sender {
+    config: RetransmitConfig,
+    next_seq: u8,
+    batch_size: usize,
+    pending: Option<Pending>,
+}
+
+impl Sender {
+    pub const fn new(config: RetransmitConfig) -> Self {
+        Self {
+            config,
+            next_seq: 0,
+            // Until the receiver told us what it can take
+            batch_size: 1,
+            pending: None,
+        }
+    }
+
+    /// How many readings fit into the next frame, as negotiated with the receiver.
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Change the timing, for example after the baud rate changed. Applies from the next
+    /// transmission on.
+    pub fn set_config(&mut self, config: RetransmitConfig) {
+        self.config = config;
+    }
+
+    /// Start sending a batch of readings and return the frame to transmit.
+    ///
+    /// Only one frame is in flight at a time, and it holds at most [`Sender::batch_size`]
+    /// readings.
+    pub fn send(&mut self, readings: &[SensorReading], now_ms: u64) -> Result<Frame, SendError> {
+        if self.pending.is_some() {
+            return Err(SendError::Busy);
+        }
+        if readings.is_empty() || readings.len() > self.batch_size {
+            return Err(SendError::InvalidBatch);
+        }
+
+        let frame = Frame::Data {
+            seq: self.next_seq,
+            readings: Readings::from_slice(readings).map_err(|_| SendError::InvalidBatch)?,
+        };
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.pending = Some(Pending {
+            frame: frame.clone(),
+            attempts: 1,
+            deadline: now_ms + self.config.ack_timeout_ms,
+        });
+        Ok(frame)
+    }
+
+    /// Whether a new batch can be sent.
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_none()
+    }
+
+    /// Handle a frame received from the other side.
+    pub fn on_frame(&mut self, frame: &Frame, now_ms: u64) -> Option<SenderEvent> {
+        let pending = self.pending.as_ref()?;
+        if frame.seq() != pending.frame.seq() {
+            // Late answer to something we already gave up on
+            return None;
+        }
+
+        match *frame {
+            Frame::Ack { seq, max_readings } => {
+                let attempts = pending.attempts;
+                self.pending = None;
+                self.batch_size = (max_readings as usize).clamp(1, MAX_READINGS_PER_FRAME);
+                Some(SenderEvent::Delivered { seq, attempts })
+            }
+            Frame::Nack { .. } => {
+                let pending = self.pending.take()?;
+                Some(self.retransmit(pending, now_ms))
+            }
+            Frame::Data { .. }
+            | Frame::Poll { .. }
+            | Frame::Idle
+            | Frame::Baud { .. }
+            | Frame::Probe { .. } => None,
+        }
+    }
+
+    /// Let time pass, retransmits once the acknowledgement is overdue.
+    pub fn poll(&mut self, now_ms: u64) -> Option<SenderEvent> {
+        if now_ms < self.next_deadline()? {
+            return None;
+        }
+        let pending = self.pending.take()?;
+        Some(self.retransmit(pending, now_ms))
+    }
+
+    /// When [`Sender::poll`] has to be called next, if anything is in flight.
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.pending.as_ref().map(|pending| pending.deadline)
+    }
+
+    fn retransmit(&mut self, mut pending: Pending, now_ms: u64) -> SenderEvent {
+        if pending.attempts >= self.config.max_attempts {
+            return SenderEvent::GaveUp {
+                seq: pending.frame.seq(),
+            };
+        }
+
+        pending.attempts += 1;
+        pending.deadline = now_ms + self.config.ack_timeout_ms;
+        let frame = pending.frame.clone();
+        self.pending = Some(pending);
+        SenderEvent::Transmit(frame)
+    }
+}
+
+/// What to do with a data frame that arrived.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Received {
+    /// Send this back to the sender.
+    pub reply: Frame,
+    /// A retransmission of a frame that was already received, its readings are not new.
+    pub duplicate: bool,
+}
+
+/// Receiving side of the stop-and-wait protocol.
+pub struct Receiver {
+    max_readings: u8,
+    last_seq: Option<u8>,
+}
+
+impl Receiver {
+    /// A receiver accepting up to `max_readings` readings per frame, which it advertises to
+    /// the sender with every acknowledgement.
+    pub const fn new(max_readings: u8) -> Self {
+        Self {
+            max_readings,
+            last_seq: None,
+        }
+    }
+
+    /// Handle a received frame, only data frames need an answer. A baud frame starts a new
+    /// stream, the next data frame is never a duplicate.
+    pub fn on_frame(&mut self, frame: &Frame) -> Option<Received> {
+        let seq = match *frame {
+            Frame::Data { seq, .. } => seq,
+            Frame::Baud { .. } => {
+                self.last_seq = None;
+                return None;
+            }
+            _ => return None,
+        };
+
+        let duplicate = self.last_seq == Some(seq);
+        self.last_seq = Some(seq);
+        Some(Received {
+            reply: Frame::Ack {
+                seq,
+                max_readings: self.max_readings,
+            },
+            duplicate,
+        })
+    }
+
+    /// Ask for a retransmission of a frame that arrived broken, instead of waiting for the
+    /// sender to time out.
+    ///
+    /// Replayed frames are answered too: they may come from a sender that restarted, which
+    /// counts on from the counter of the nack (see `src/auth.rs`).
+    pub fn on_error(&self, error: &FrameError) -> Option<Frame> {
+        match *error {
+            FrameError::CrcMismatch { seq, .. } | FrameError::Replayed { seq, .. } => {
+                Some(Frame::Nack { seq })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Default for Receiver {
+    fn default() -> Self {
+        Self::new(MAX_READINGS_PER_FRAME as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_reading as reading;
+
+    fn ack(seq: u8) -> Frame {
+        Frame::Ack {
+            seq,
+            max_readings: 1,
+        }
+    }
+
+    #[test]
+    fn acknowledged_frame_is_delivered() {
+        let mut sender = Sender::new(RetransmitConfig::default());
+        let mut receiver = Receiver::new(1);
+
+        let frame = sender.send(&[reading(1)], 0).unwrap();
+        assert!(!sender.is_idle());
+        assert_eq!(sender.send(&[reading(2)], 1), Err(SendError::Busy));
+
+        let received = receiver.on_frame(&frame).unwrap();
+        assert!(!received.duplicate);
+        assert_eq!(received.reply, ack(0));
+        assert_eq!(
+            sender.on_frame(&received.reply, 5),
+            Some(SenderEvent::Delivered {
+                seq: 0,
+                attempts: 1
+            })
+        );
+        assert!(sender.is_idle());
+        assert_eq!(sender.next_deadline(), None);
+    }
+
+    #[test]
+    fn batch_size_follows_receiver() {
+        let mut sender = Sender::new(RetransmitConfig::default());
+        let mut receiver = Receiver::new(4);
+        let readings: [SensorReading; 6] = core::array::from_fn(|i| reading(i as u32));
+
+        // Only one reading per frame until the receiver has spoken
+        assert_eq!(sender.batch_size(), 1);
+        assert_eq!(sender.send(&readings[..2], 0), Err(SendError::InvalidBatch));
+        assert_eq!(sender.send(&[], 0), Err(SendError::InvalidBatch));
+
+        let frame = sender.send(&readings[..1], 0).unwrap();
+        let reply = receiver.on_frame(&frame).unwrap().reply;
+        sender.on_frame(&reply, 1);
+        assert_eq!(sender.batch_size(), 4);
+
+        let frame = sender.send(&readings[1..5], 2).unwrap();
+        let Frame::Data { readings: sent, .. } = &frame else {
+            panic!("expected a data frame");
+        };
+        assert_eq!(sent.as_slice(), &readings[1..5]);
+
+        // A receiver claiming more than any frame can hold doesn't break the sender
+        sender.on_frame(
+            &Frame::Ack {
+                seq: 1,
+                max_readings: 200,
+            },
+            3,
+        );
+        assert_eq!(sender.batch_size(), MAX_READINGS_PER_FRAME);
+    }
+
+    #[test]
+    fn retransmits_on_timeout_then_gives_up() {
+        let config = RetransmitConfig {
+            ack_timeout_ms: 10,
+            max_attempts: 3,
+        };
+        let mut sender = Sender::new(config);
+
+        let frame = sender.send(&[reading(1)], 0).unwrap();
+        assert_eq!(sender.poll(9), None);
+        assert_eq!(sender.poll(10), Some(SenderEvent::Transmit(frame.clone())));
+        assert_eq!(sender.next_deadline(), Some(20));
+        assert_eq!(sender.poll(20), Some(SenderEvent::Transmit(frame)));
+        assert_eq!(sender.poll(30), Some(SenderEvent::GaveUp { seq: 0 }));
+        assert!(sender.is_idle());
+
+        // The next frame gets a fresh sequence number, a late ack of the old one is ignored
+        let next = sender.send(&[reading(2)], 30).unwrap();
+        assert_eq!(next.seq(), 1);
+        assert_eq!(sender.on_frame(&ack(0), 31), None);
+        assert!(!sender.is_idle());
+    }
+
+    #[test]
+    fn nack_retransmits_right_away() {
+        let mut sender = Sender::new(RetransmitConfig::default());
+        let receiver = Receiver::new(1);
+
+        let frame = sender.send(&[reading(1)], 0).unwrap();
+        let nack = receiver
+            .on_error(&FrameError::CrcMismatch {
+                seq: 0,
+                received: 0x1234,
+                expected: 0x4321,
+            })
+            .unwrap();
+        assert_eq!(
+            sender.on_frame(&nack, 3),
+            Some(SenderEvent::Transmit(frame))
+        );
+        assert_eq!(sender.next_deadline(), Some(53));
+    }
+
+    #[test]
+    fn lost_ack_does_not_duplicate_readings() {
+        let mut sender = Sender::new(RetransmitConfig::default());
+        let mut receiver = Receiver::new(1);
+
+        let frame = sender.send(&[reading(1)], 0).unwrap();
+        // The first ack gets lost on the way back
+        let first = receiver.on_frame(&frame).unwrap();
+        assert!(!first.duplicate);
+
+        let Some(SenderEvent::Transmit(again)) = sender.poll(100) else {
+            panic!("expected a retransmission");
+        };
+        let second = receiver.on_frame(&again).unwrap();
+        assert!(second.duplicate);
+        assert_eq!(
+            sender.on_frame(&second.reply, 101),
+            Some(SenderEvent::Delivered {
+                seq: 0,
+                attempts: 2
+            })
+        );
+    }
+
+    #[test]
+    fn a_restarted_sender_is_not_taken_for_a_duplicate() {
+        let mut receiver = Receiver::new(1);
+        let mut sender = Sender::new(RetransmitConfig::default());
+        let frame = sender.send(&[reading(1)], 0).unwrap();
+        assert!(!receiver.on_frame(&frame).unwrap().duplicate);
+
+        // Starts over at the same sequence number, after negotiating the rate like every boot
+        let mut restarted = Sender::new(RetransmitConfig::default());
+        let first = restarted.send(&[reading(2)], 1_000).unwrap();
+        assert_eq!(first.seq(), frame.seq());
+        assert_eq!(receiver.on_frame(&Frame::Baud { rate: 19_200 }), None);
+        assert!(!receiver.on_frame(&first).unwrap().duplicate);
+
+        // Its retransmission still is one
+        assert!(receiver.on_frame(&first).unwrap().duplicate);
+    }
+}
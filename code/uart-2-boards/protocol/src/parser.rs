@@ -0,0 +1,468 @@
+use core::fmt;
+
+use heapless::Vec;
+
+#[cfg(feature = "auth")]
+use crate::Key;
+use crate::{
+    crc::{crc16_update, CRC16_INIT},
+    Frame, FrameKind, Readings, SensorReading, AUTHENTICATED, AUTH_LENGTH, DIRECT_ADDRESS,
+    END_BYTE, ESCAPE_BYTE, ESCAPE_XOR, MAX_PAYLOAD_LENGTH, PROBE_PATTERN, PROTOCOL_VERSION,
+    READING_LENGTH, START_BYTE,
+};
+
+/// Why a frame was thrown away
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    InvalidLength(u8),
+    BufferOverflow,
+    /// `seq` is what the broken frame claimed to be, it is not protected by anything
+    CrcMismatch {
+        seq: u8,
+        received: u16,
+        expected: u16,
+    },
+    UnknownKind(u8),
+    /// The sender speaks a different version of the protocol
+    UnsupportedVersion(u8),
+    InvalidEndByte(u8),
+    InsufficientData(usize),
+    /// A start or end byte showed up in the middle of a frame, or the line went quiet
+    /// (see [`FrameParser::on_timeout`]): some bytes got lost
+    Truncated,
+    /// An escape byte was followed by something that never needs escaping
+    InvalidEscape(u8),
+    /// The receiver only accepts authenticated frames, this one isn't
+    MissingTag,
+    /// The frame is authenticated, but the receiver has no key to check it
+    UnexpectedTag,
+    /// The tag doesn't match: forged, or made with a different key
+    BadTag {
+        seq: u8,
+    },
+    /// Authentic, but the counter isn't newer than that of the last frame accepted
+    Replayed {
+        seq: u8,
+        counter: u32,
+    },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidLength(length) => write!(f, "Invalid data length: {}", length),
+            Self::BufferOverflow => write!(f, "Buffer overflow during data reading"),
+            Self::CrcMismatch {
+                seq,
+                received,
+                expected,
+            } => write!(
+                f,
+                "CRC mismatch in frame {}: got {:#06X}, expected {:#06X}",
+                seq, received, expected
+            ),
+            Self::UnknownKind(kind) => write!(f, "Unknown frame kind: {:#04X}", kind),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported protocol version {:#04X}, expected {:#04X}",
+                version, PROTOCOL_VERSION
+            ),
+            Self::InvalidEndByte(byte) => write!(f, "Invalid end byte: {:#04X}", byte),
+            Self::InsufficientData(length) => {
+                write!(f, "Insufficient data in buffer: {} bytes", length)
+            }
+            Self::Truncated => write!(f, "Frame truncated"),
+            Self::InvalidEscape(byte) => write!(f, "Invalid escaped byte: {:#04X}", byte),
+            Self::MissingTag => write!(f, "Frame not authenticated"),
+            Self::UnexpectedTag => write!(f, "Authenticated frame, but no key to check it"),
+            Self::BadTag { seq } => write!(f, "Wrong tag in frame {}", seq),
+            Self::Replayed { seq, counter } => {
+                write!(f, "Replayed frame {} with counter {}", seq, counter)
+            }
+        }
+    }
+}
+
+/// Frame parser state machine
+#[derive(Debug, PartialEq)]
+pub enum ParseState {
+    WaitingForStart,
+    ReadingVersion,
+    ReadingAddress,
+    ReadingKind,
+    ReadingSequence,
+    ReadingLength,
+    ReadingData,
+    ReadingAuthentication,
+    ReadingCrcHigh,
+    ReadingCrcLow,
+    ReadingEnd,
+}
+
+/// Decoder for incoming frames, fed one byte at a time
+pub struct FrameParser {
+    state: ParseState,
+    buffer: Vec<u8, MAX_PAYLOAD_LENGTH>,
+    /// Address of the frame returned last, kept across resets
+    address: u8,
+    /// Address of the frame in progress
+    pending_address: u8,
+    kind: FrameKind,
+    /// Whether the frame in progress carries a counter and a tag
+    authenticated: bool,
+    seq: u8,
+    data_length: u8,
+    bytes_read: usize,
+    /// Counter and tag of the frame in progress
+    authentication: [u8; AUTH_LENGTH],
+    crc: u16,
+    received_crc: u16,
+    escaped: bool,
+    #[cfg(feature = "auth")]
+    key: Option<Key>,
+    /// Counter of the last authenticated frame accepted, kept across resets
+    last_counter: Option<u32>,
+}
+
+impl FrameParser {
+    pub fn new() -> Self {
+        Self {
+            state: ParseState::WaitingForStart,
+            buffer: Vec::new(),
+            address: DIRECT_ADDRESS,
+            pending_address: DIRECT_ADDRESS,
+            kind: FrameKind::Data,
+            authenticated: false,
+            seq: 0,
+            data_length: 0,
+            bytes_read: 0,
+            authentication: [0; AUTH_LENGTH],
+            crc: CRC16_INIT,
+            received_crc: 0,
+            escaped: false,
+            #[cfg(feature = "auth")]
+            key: None,
+            last_counter: None,
+        }
+    }
+
+    /// A parser accepting only frames authenticated with `key`, see [`crate::auth`].
+    #[cfg(feature = "auth")]
+    pub fn authenticated(key: Key) -> Self {
+        Self {
+            key: Some(key),
+            ..Self::new()
+        }
+    }
+
+    pub fn state(&self) -> &ParseState {
+        &self.state
+    }
+
+    pub fn reset(&mut self) {
+        self.state = ParseState::WaitingForStart;
+        self.buffer.clear();
+        self.pending_address = DIRECT_ADDRESS;
+        self.kind = FrameKind::Data;
+        self.authenticated = false;
+        self.seq = 0;
+        self.data_length = 0;
+        self.bytes_read = 0;
+        self.crc = CRC16_INIT;
+        self.received_crc = 0;
+        self.escaped = false;
+    }
+
+    /// Address of the frame [`FrameParser::process_byte`] returned last.
+    ///
+    /// On the point-to-point link this is always [`DIRECT_ADDRESS`], on a bus it tells who the
+    /// frame is for or who sent it.
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Counter of the last authenticated frame accepted, `None` before the first and without a
+    /// key. Frames with a counter up to this one are rejected as [`FrameError::Replayed`].
+    pub fn last_counter(&self) -> Option<u32> {
+        self.last_counter
+    }
+
+    /// Count on from `counter`, the [`FrameParser::last_counter`] of a parser from before a
+    /// restart, so frames recorded before it stay rejected. A lower one changes nothing.
+    pub fn resume_counter(&mut self, counter: Option<u32>) {
+        self.last_counter = self.last_counter.max(counter);
+    }
+
+    /// The line went quiet in the middle of a frame, the rest of it is not coming anymore.
+    ///
+    /// Resets the parser and returns [`FrameError::Truncated`] if a frame was in progress.
+    pub fn on_timeout(&mut self) -> Option<FrameError> {
+        if self.state == ParseState::WaitingForStart {
+            return None;
+        }
+        self.reset();
+        Some(FrameError::Truncated)
+    }
+
+    /// Feed the next received byte. Returns the frame once it arrived complete and valid.
+    ///
+    /// After an error the parser is reset and waits for the next start byte. A start byte in the
+    /// middle of a frame reports [`FrameError::Truncated`] and already begins the new frame.
+    pub fn process_byte(&mut self, byte: u8) -> Result<Option<Frame>, FrameError> {
+        if self.state == ParseState::WaitingForStart {
+            return self.step(byte);
+        }
+
+        if byte == START_BYTE {
+            self.reset();
+            self.step(byte)?;
+            return Err(FrameError::Truncated);
+        }
+
+        let result = self.unescape(byte);
+        if result.is_err() {
+            self.reset();
+        }
+        result
+    }
+
+    /// Undo the byte stuffing of everything between start and end byte.
+    fn unescape(&mut self, byte: u8) -> Result<Option<Frame>, FrameError> {
+        if self.state == ParseState::ReadingEnd {
+            return self.step(byte);
+        }
+
+        if self.escaped {
+            self.escaped = false;
+            let unescaped = byte ^ ESCAPE_XOR;
+            if !matches!(unescaped, START_BYTE | END_BYTE | ESCAPE_BYTE) {
+                return Err(FrameError::InvalidEscape(byte));
+            }
+            return self.step(unescaped);
+        }
+
+        match byte {
+            ESCAPE_BYTE => {
+                self.escaped = true;
+                Ok(None)
+            }
+            END_BYTE => Err(FrameError::Truncated),
+            _ => self.step(byte),
+        }
+    }
+
+    fn step(&mut self, byte: u8) -> Result<Option<Frame>, FrameError> {
+        match self.state {
+            ParseState::WaitingForStart => {
+                if byte == START_BYTE {
+                    self.buffer.clear();
+                    self.state = ParseState::ReadingVersion;
+                }
+            }
+
+            ParseState::ReadingVersion => {
+                if byte != PROTOCOL_VERSION {
+                    return Err(FrameError::UnsupportedVersion(byte));
+                }
+                self.crc = crc16_update(CRC16_INIT, byte); // Start CRC calculation
+                self.state = ParseState::ReadingAddress;
+            }
+
+            ParseState::ReadingAddress => {
+                self.pending_address = byte;
+                self.crc = crc16_update(self.crc, byte);
+                self.state = ParseState::ReadingKind;
+            }
+
+            ParseState::ReadingKind => {
+                self.kind = FrameKind::try_from(byte & !AUTHENTICATED)?;
+                self.authenticated = byte & AUTHENTICATED != 0;
+                match (self.authenticated, self.has_key()) {
+                    (true, false) => return Err(FrameError::UnexpectedTag),
+                    (false, true) => return Err(FrameError::MissingTag),
+                    _ => {}
+                }
+                self.crc = crc16_update(self.crc, byte);
+                self.state = ParseState::ReadingSequence;
+            }
+
+            ParseState::ReadingSequence => {
+                self.seq = byte;
+                self.crc = crc16_update(self.crc, byte);
+                self.state = ParseState::ReadingLength;
+            }
+
+            ParseState::ReadingLength => {
+                self.data_length = byte;
+                self.crc = crc16_update(self.crc, byte);
+                self.bytes_read = 0;
+                self.buffer.clear();
+
+                let length = self.data_length as usize;
+                let valid = match self.kind {
+                    FrameKind::Data => {
+                        length > 0 && length <= MAX_PAYLOAD_LENGTH && length % READING_LENGTH == 0
+                    }
+                    FrameKind::Ack | FrameKind::Poll => length == 1,
+                    FrameKind::Nack | FrameKind::Idle => length == 0,
+                    FrameKind::Baud => length == 4,
+                    FrameKind::Probe => length == PROBE_PATTERN.len(),
+                };
+                if !valid {
+                    return Err(FrameError::InvalidLength(self.data_length));
+                }
+                self.state = if self.data_length == 0 {
+                    self.after_payload()
+                } else {
+                    ParseState::ReadingData
+                };
+            }
+
+            ParseState::ReadingData => {
+                self.buffer
+                    .push(byte)
+                    .map_err(|_| FrameError::BufferOverflow)?;
+
+                self.crc = crc16_update(self.crc, byte);
+                self.bytes_read += 1;
+
+                if self.bytes_read >= self.data_length as usize {
+                    self.state = self.after_payload();
+                }
+            }
+
+            ParseState::ReadingAuthentication => {
+                self.authentication[self.bytes_read] = byte;
+                self.crc = crc16_update(self.crc, byte);
+                self.bytes_read += 1;
+
+                if self.bytes_read >= AUTH_LENGTH {
+                    self.state = ParseState::ReadingCrcHigh;
+                }
+            }
+
+            ParseState::ReadingCrcHigh => {
+                self.received_crc = (byte as u16) << 8;
+                self.state = ParseState::ReadingCrcLow;
+            }
+
+            ParseState::ReadingCrcLow => {
+                self.received_crc |= byte as u16;
+                if self.received_crc != self.crc {
+                    return Err(FrameError::CrcMismatch {
+                        seq: self.seq,
+                        received: self.received_crc,
+                        expected: self.crc,
+                    });
+                }
+                self.state = ParseState::ReadingEnd;
+            }
+
+            ParseState::ReadingEnd => {
+                if byte != END_BYTE {
+                    return Err(FrameError::InvalidEndByte(byte));
+                }
+                #[cfg(feature = "auth")]
+                self.verify()?;
+                // Frame complete, parse the payload
+                let frame = self.parse_frame();
+                self.address = self.pending_address;
+                self.reset();
+                return frame.map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn after_payload(&mut self) -> ParseState {
+        if self.authenticated {
+            self.bytes_read = 0;
+            ParseState::ReadingAuthentication
+        } else {
+            ParseState::ReadingCrcHigh
+        }
+    }
+
+    #[cfg(feature = "auth")]
+    fn has_key(&self) -> bool {
+        self.key.is_some()
+    }
+
+    #[cfg(not(feature = "auth"))]
+    fn has_key(&self) -> bool {
+        false
+    }
+
+    /// Check the tag and the counter of an authenticated frame.
+    #[cfg(feature = "auth")]
+    fn verify(&mut self) -> Result<(), FrameError> {
+        let Some(key) = self.key else {
+            return Ok(());
+        };
+        let header = [
+            PROTOCOL_VERSION,
+            self.pending_address,
+            self.kind as u8 | AUTHENTICATED,
+            self.seq,
+            self.data_length,
+        ];
+        let (counter, tag) = self.authentication.split_at(4);
+        if !key.verify(&[&header, &self.buffer, counter], tag) {
+            return Err(FrameError::BadTag { seq: self.seq });
+        }
+
+        let counter = u32::from_be_bytes([counter[0], counter[1], counter[2], counter[3]]);
+        if self.last_counter.is_some_and(|last| counter <= last) {
+            return Err(FrameError::Replayed {
+                seq: self.seq,
+                counter,
+            });
+        }
+        self.last_counter = Some(counter);
+        Ok(())
+    }
+
+    fn parse_frame(&self) -> Result<Frame, FrameError> {
+        let seq = self.seq;
+        match self.kind {
+            FrameKind::Data => {
+                let mut readings = Readings::new();
+                for chunk in self.buffer.chunks(READING_LENGTH) {
+                    let bytes = chunk
+                        .try_into()
+                        .map_err(|_| FrameError::InsufficientData(self.buffer.len()))?;
+                    readings
+                        .push(SensorReading::from_bytes(bytes))
+                        .map_err(|_| FrameError::BufferOverflow)?;
+                }
+                Ok(Frame::Data { seq, readings })
+            }
+            FrameKind::Ack => Ok(Frame::Ack {
+                seq,
+                max_readings: self.buffer[0],
+            }),
+            FrameKind::Nack => Ok(Frame::Nack { seq }),
+            FrameKind::Poll => Ok(Frame::Poll {
+                max_readings: self.buffer[0],
+            }),
+            FrameKind::Idle => Ok(Frame::Idle),
+            FrameKind::Baud => Ok(Frame::Baud {
+                rate: u32::from_be_bytes([
+                    self.buffer[0],
+                    self.buffer[1],
+                    self.buffer[2],
+                    self.buffer[3],
+                ]),
+            }),
+            // The CRC already made sure the pattern arrived as sent
+            FrameKind::Probe => Ok(Frame::Probe { seq }),
+        }
+    }
+}
+
+impl Default for FrameParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,323 @@
+//! When the sending board samples its sensors and when it may transmit.
+//!
+//! Every sensor has its own [`SensorSchedule`], so fast and slow sensors share one link. The
+//! [`Scheduler`] tells which sensor is due, the readings wait in a [`ReadingQueue`] until they
+//! are sent, and the [`RateLimiter`] keeps the frames within the bandwidth of the link.
+//!
+//! Like the rest of the crate this only works with timestamps in milliseconds, the board does
+//! the waiting.
+
+use heapless::Deque;
+
+use crate::{Readings, SensorReading, MAX_READINGS_PER_FRAME};
+
+/// How often one sensor is sampled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SensorSchedule {
+    pub sensor_id: u8,
+    pub period_ms: u32,
+    /// Offset of the first sample, so sensors with the same period don't all come at once.
+    pub phase_ms: u32,
+}
+
+impl SensorSchedule {
+    pub const fn every(sensor_id: u8, period_ms: u32) -> Self {
+        Self {
+            sensor_id,
+            period_ms,
+            phase_ms: 0,
+        }
+    }
+
+    pub const fn with_phase(self, phase_ms: u32) -> Self {
+        Self { phase_ms, ..self }
+    }
+}
+
+/// Picks the sensors due for sampling.
+pub struct Scheduler<const N: usize> {
+    schedules: [SensorSchedule; N],
+    next_due: [u64; N],
+    missed: u32,
+}
+
+impl<const N: usize> Scheduler<N> {
+    /// Starts counting the periods and phases from `now_ms`.
+    pub fn new(schedules: [SensorSchedule; N], now_ms: u64) -> Self {
+        Self {
+            schedules,
+            next_due: schedules.map(|schedule| now_ms + schedule.phase_ms as u64),
+            missed: 0,
+        }
+    }
+
+    /// The sensor that is due the longest, if any. Call it until it returns `None`.
+    ///
+    /// A sensor that fell behind by more than a period is sampled once and then stays in its
+    /// rhythm, the samples in between are counted as [`Scheduler::missed`].
+    pub fn next_due(&mut self, now_ms: u64) -> Option<u8> {
+        let (index, &due) = self
+            .next_due
+            .iter()
+            .enumerate()
+            .filter(|(_, &due)| due <= now_ms)
+            .min_by_key(|(_, &due)| due)?;
+
+        let period = (self.schedules[index].period_ms as u64).max(1);
+        let behind = (now_ms - due) / period;
+        self.missed += behind as u32;
+        self.next_due[index] = due + (behind + 1) * period;
+        Some(self.schedules[index].sensor_id)
+    }
+
+    /// When the next sensor is due, `None` without any sensors.
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.next_due.iter().copied().min()
+    }
+
+    /// Samples skipped because the board was busy for longer than a period.
+    pub fn missed(&self) -> u32 {
+        self.missed
+    }
+}
+
+/// Readings waiting to be sent, oldest first.
+pub struct ReadingQueue<const N: usize> {
+    readings: Deque<SensorReading, N>,
+}
+
+impl<const N: usize> ReadingQueue<N> {
+    pub const fn new() -> Self {
+        Self {
+            readings: Deque::new(),
+        }
+    }
+
+    /// Queue a reading. A full queue makes room by dropping the oldest reading, which is
+    /// returned.
+    pub fn push(&mut self, reading: SensorReading) -> Option<SensorReading> {
+        let dropped = if self.readings.is_full() {
+            self.readings.pop_front()
+        } else {
+            None
+        };
+        // There is room now
+        _ = self.readings.push_back(reading);
+        dropped
+    }
+
+    /// Take up to `max` of the oldest readings, never more than fit into a frame.
+    pub fn take(&mut self, max: usize) -> Readings {
+        let count = max.min(MAX_READINGS_PER_FRAME);
+        let mut readings = Readings::new();
+        while readings.len() < count {
+            let Some(reading) = self.readings.pop_front() else {
+                break;
+            };
+            // Can't overflow, count is at most a full frame
+            _ = readings.push(reading);
+        }
+        readings
+    }
+
+    pub fn len(&self) -> usize {
+        self.readings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.readings.is_empty()
+    }
+}
+
+impl<const N: usize> Default for ReadingQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Token bucket over the bytes put on the wire.
+///
+/// Sending is allowed while the budget isn't overdrawn. Every frame is paid for after it was
+/// sent, retransmissions included, and an overdrawn budget delays the next frame until it has
+/// recovered. So a burst of up to `burst_bytes` goes out right away, and in the long run no
+/// more than `bytes_per_second` are sent.
+pub struct RateLimiter {
+    bytes_per_second: u32,
+    /// In thousandths of a byte, the refill per millisecond is then `bytes_per_second`
+    burst: i64,
+    balance: i64,
+    updated_ms: u64,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u32, burst_bytes: u32, now_ms: u64) -> Self {
+        let burst = burst_bytes as i64 * 1000;
+        Self {
+            bytes_per_second: bytes_per_second.max(1),
+            burst,
+            balance: burst,
+            updated_ms: now_ms,
+        }
+    }
+
+    /// Whether the next frame may go out.
+    pub fn is_ready(&mut self, now_ms: u64) -> bool {
+        self.refill(now_ms);
+        self.balance >= 0
+    }
+
+    /// Pay for `bytes` sent.
+    pub fn record(&mut self, bytes: usize, now_ms: u64) {
+        self.refill(now_ms);
+        self.balance -= bytes as i64 * 1000;
+    }
+
+    /// When the budget isn't overdrawn anymore, in the past if it isn't now.
+    pub fn ready_at(&self) -> u64 {
+        if self.balance >= 0 {
+            return self.updated_ms;
+        }
+        let rate = self.bytes_per_second as i64;
+        self.updated_ms + ((-self.balance + rate - 1) / rate) as u64
+    }
+
+    fn refill(&mut self, now_ms: u64) {
+        let elapsed = now_ms.saturating_sub(self.updated_ms) as i64;
+        self.balance = (self.balance + elapsed * self.bytes_per_second as i64).min(self.burst);
+        self.updated_ms = self.updated_ms.max(now_ms);
+    }
+}
+
+/// Bytes per second a UART moves at `baud_rate` with 8N1 framing.
+pub const fn uart_bytes_per_second(baud_rate: u32) -> u32 {
+    // Start and stop bit on top of every byte
+    baud_rate / 10
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_reading as reading, SENSOR_HUMIDITY, SENSOR_LIGHT, SENSOR_TEMPERATURE};
+
+    fn due_until(scheduler: &mut Scheduler<3>, now_ms: u64) -> heapless::Vec<u8, 16> {
+        core::iter::from_fn(|| scheduler.next_due(now_ms)).collect()
+    }
+
+    #[test]
+    fn sensors_follow_their_periods_and_phases() {
+        let mut scheduler = Scheduler::new(
+            [
+                SensorSchedule::every(SENSOR_LIGHT, 100),
+                SensorSchedule::every(SENSOR_TEMPERATURE, 500).with_phase(50),
+                SensorSchedule::every(SENSOR_HUMIDITY, 1000).with_phase(250),
+            ],
+            1000,
+        );
+
+        let mut samples: heapless::Vec<(u64, u8), 32> = heapless::Vec::new();
+        let mut now = 1000;
+        while now < 2000 {
+            for sensor in due_until(&mut scheduler, now) {
+                samples.push((now - 1000, sensor)).unwrap();
+            }
+            now = scheduler.next_deadline().unwrap();
+        }
+
+        let count = |id| samples.iter().filter(|(_, sensor)| *sensor == id).count();
+        assert_eq!(count(SENSOR_LIGHT), 10);
+        assert_eq!(count(SENSOR_TEMPERATURE), 2);
+        assert_eq!(count(SENSOR_HUMIDITY), 1);
+        assert_eq!(
+            samples[..3],
+            [
+                (0, SENSOR_LIGHT),
+                (50, SENSOR_TEMPERATURE),
+                (100, SENSOR_LIGHT)
+            ]
+        );
+        assert!(samples.contains(&(550, SENSOR_TEMPERATURE)));
+        assert!(samples.contains(&(250, SENSOR_HUMIDITY)));
+        assert_eq!(scheduler.missed(), 0);
+    }
+
+    #[test]
+    fn late_sensors_keep_their_rhythm() {
+        let mut scheduler = Scheduler::new(
+            [
+                SensorSchedule::every(SENSOR_LIGHT, 100),
+                SensorSchedule::every(SENSOR_TEMPERATURE, 1000),
+                SensorSchedule::every(SENSOR_HUMIDITY, 1000).with_phase(10),
+            ],
+            0,
+        );
+        assert_eq!(
+            due_until(&mut scheduler, 0),
+            [SENSOR_LIGHT, SENSOR_TEMPERATURE]
+        );
+
+        // Busy for a while: each overdue sensor is sampled once, the one waiting longest first
+        assert_eq!(
+            due_until(&mut scheduler, 350),
+            [SENSOR_HUMIDITY, SENSOR_LIGHT]
+        );
+        assert_eq!(scheduler.missed(), 2);
+        assert_eq!(scheduler.next_deadline(), Some(400));
+    }
+
+    #[test]
+    fn queue_drops_oldest_and_batches() {
+        let mut queue: ReadingQueue<4> = ReadingQueue::new();
+        for timestamp in 0..4 {
+            assert_eq!(queue.push(reading(timestamp)), None);
+        }
+        assert_eq!(queue.push(reading(4)), Some(reading(0)));
+        assert_eq!(queue.len(), 4);
+
+        assert_eq!(queue.take(3), [reading(1), reading(2), reading(3)]);
+        assert_eq!(queue.take(3), [reading(4)]);
+        assert!(queue.is_empty());
+        assert!(queue.take(3).is_empty());
+    }
+
+    #[test]
+    fn rate_limiter_allows_bursts_then_the_rate() {
+        // 1000 bytes per second, that's a byte per millisecond
+        let mut limiter = RateLimiter::new(1000, 100, 0);
+        assert!(limiter.is_ready(0));
+        limiter.record(60, 0);
+        assert!(limiter.is_ready(0));
+        limiter.record(60, 0);
+        assert!(!limiter.is_ready(0));
+        assert_eq!(limiter.ready_at(), 20);
+        assert!(!limiter.is_ready(19));
+        assert!(limiter.is_ready(20));
+
+        // Idle time refills the budget, but not beyond the burst
+        assert!(limiter.is_ready(10_000));
+        limiter.record(100, 10_000);
+        assert!(limiter.is_ready(10_000));
+        limiter.record(1, 10_000);
+        assert_eq!(limiter.ready_at(), 10_001);
+    }
+
+    #[test]
+    fn rate_limiter_holds_the_long_term_rate() {
+        let bytes_per_second = uart_bytes_per_second(115_200) / 2;
+        let mut limiter = RateLimiter::new(bytes_per_second, 200, 0);
+        let mut now = 0;
+        let mut sent = 0;
+        while now < 10_000 {
+            if limiter.is_ready(now) {
+                limiter.record(95, now);
+                sent += 95;
+            } else {
+                now = limiter.ready_at();
+            }
+        }
+        assert!(
+            sent <= 10 * bytes_per_second as usize + 200 + 95,
+            "{sent} bytes"
+        );
+        assert!(sent >= 10 * bytes_per_second as usize - 95, "{sent} bytes");
+    }
+}
@@ -0,0 +1,117 @@
+//! Registry of the sensors the boards know about.
+//!
+//! Only the ID goes over the wire. Everything needed to generate, check or display a reading is
+//! looked up here, so adding a sensor means adding one line to [`SENSORS`].
+
+pub const SENSOR_TEMPERATURE: u8 = 0x01;
+pub const SENSOR_HUMIDITY: u8 = 0x02;
+pub const SENSOR_PRESSURE: u8 = 0x03;
+pub const SENSOR_LIGHT: u8 = 0x04;
+pub const SENSOR_VOLTAGE: u8 = 0x05;
+
+/// Static description of one sensor type
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SensorInfo {
+    pub id: u8,
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub icon: &'static str,
+    /// Smallest value the sensor can report
+    pub min: f32,
+    /// Largest value the sensor can report
+    pub max: f32,
+    /// Decimal places worth showing
+    pub precision: u8,
+}
+
+impl SensorInfo {
+    /// Whether `value` is something the sensor can actually measure.
+    pub fn in_range(&self, value: f32) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+}
+
+/// All known sensors, ordered by ID.
+pub const SENSORS: [SensorInfo; 5] = [
+    SensorInfo {
+        id: SENSOR_TEMPERATURE,
+        name: "Temperature",
+        unit: "°C",
+        icon: "🌡️",
+        min: -40.0,
+        max: 85.0,
+        precision: 1,
+    },
+    SensorInfo {
+        id: SENSOR_HUMIDITY,
+        name: "Humidity",
+        unit: "%",
+        icon: "💧",
+        min: 0.0,
+        max: 100.0,
+        precision: 1,
+    },
+    SensorInfo {
+        id: SENSOR_PRESSURE,
+        name: "Pressure",
+        unit: "hPa",
+        icon: "🌀",
+        min: 300.0,
+        max: 1100.0,
+        precision: 1,
+    },
+    SensorInfo {
+        id: SENSOR_LIGHT,
+        name: "Light",
+        unit: "lx",
+        icon: "💡",
+        min: 0.0,
+        max: 65535.0,
+        precision: 0,
+    },
+    SensorInfo {
+        id: SENSOR_VOLTAGE,
+        name: "Voltage",
+        unit: "V",
+        icon: "🔋",
+        min: 0.0,
+        max: 5.0,
+        precision: 2,
+    },
+];
+
+/// Position of a sensor in [`SENSORS`], handy for per-sensor arrays.
+pub fn sensor_index(id: u8) -> Option<usize> {
+    SENSORS.iter().position(|sensor| sensor.id == id)
+}
+
+/// Registry entry of a sensor, `None` for IDs nobody registered.
+pub fn sensor_info(id: u8) -> Option<&'static SensorInfo> {
+    sensor_index(id).map(|index| &SENSORS[index])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_unique_and_ordered() {
+        assert!(SENSORS.windows(2).all(|pair| pair[0].id < pair[1].id));
+    }
+
+    #[test]
+    fn lookup_by_id() {
+        assert_eq!(sensor_info(SENSOR_PRESSURE).unwrap().unit, "hPa");
+        assert_eq!(sensor_index(SENSOR_VOLTAGE), Some(4));
+        assert_eq!(sensor_info(0xFF), None);
+    }
+
+    #[test]
+    fn range_check() {
+        let humidity = sensor_info(SENSOR_HUMIDITY).unwrap();
+        assert!(humidity.in_range(45.0));
+        assert!(humidity.in_range(100.0));
+        assert!(!humidity.in_range(-0.1));
+        assert!(!humidity.in_range(f32::NAN));
+    }
+}
@@ -0,0 +1,314 @@
+//! Link and sensor statistics collected by the receiving board.
+
+use core::fmt;
+
+use crate::{sensor_index, FrameError, SensorReading, SENSORS};
+
+/// What was received from one sensor so far
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SensorStatistics {
+    pub count: u32,
+    pub last: f32,
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+}
+
+impl SensorStatistics {
+    const EMPTY: Self = Self {
+        count: 0,
+        last: 0.0,
+        min: f32::INFINITY,
+        max: f32::NEG_INFINITY,
+        mean: 0.0,
+    };
+
+    fn record(&mut self, value: f32) {
+        self.count += 1;
+        self.last = value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        // Running mean, a sum would lose precision after a while
+        self.mean += (value - self.mean) / self.count as f32;
+    }
+}
+
+/// Counters for everything that arrived over the link, good or bad.
+///
+/// Lost readings are detected by gaps in [`SensorReading::timestamp`], which the sender counts up
+/// by one for every reading, no matter from which sensor.
+#[derive(Clone, Debug)]
+pub struct Statistics {
+    pub sensors: [SensorStatistics; SENSORS.len()],
+    pub unknown_sensor_count: u32,
+    pub total_frames: u32,
+    pub total_readings: u32,
+    /// Retransmissions of frames that were already received
+    pub duplicates: u32,
+    /// Readings missing according to the timestamps
+    pub lost_readings: u32,
+    pub crc_errors: u32,
+    /// Bad end byte, invalid length, unknown frame kind and the like
+    pub framing_errors: u32,
+    pub truncated_frames: u32,
+    pub version_mismatches: u32,
+    /// Frames without the right tag, or with one when none was expected
+    pub auth_failures: u32,
+    /// Authentic frames that were already received before
+    pub replayed_frames: u32,
+    last_timestamp: Option<u32>,
+    interval_start_ms: u64,
+    interval_frames: u32,
+}
+
+impl Statistics {
+    pub fn new(now_ms: u64) -> Self {
+        Self {
+            sensors: [SensorStatistics::EMPTY; SENSORS.len()],
+            unknown_sensor_count: 0,
+            total_frames: 0,
+            total_readings: 0,
+            duplicates: 0,
+            lost_readings: 0,
+            crc_errors: 0,
+            framing_errors: 0,
+            truncated_frames: 0,
+            version_mismatches: 0,
+            auth_failures: 0,
+            replayed_frames: 0,
+            last_timestamp: None,
+            interval_start_ms: now_ms,
+            interval_frames: 0,
+        }
+    }
+
+    /// Record a data frame with all its readings.
+    pub fn record_frame(&mut self, readings: &[SensorReading]) {
+        self.total_frames += 1;
+        self.interval_frames += 1;
+        for reading in readings {
+            self.record_reading(reading);
+        }
+    }
+
+    fn record_reading(&mut self, reading: &SensorReading) {
+        self.total_readings += 1;
+
+        match sensor_index(reading.sensor_id) {
+            Some(index) => self.sensors[index].record(reading.value),
+            None => self.unknown_sensor_count += 1,
+        }
+
+        if let Some(last) = self.last_timestamp {
+            // Going backwards means the sender restarted, that's not a gap
+            if reading.timestamp > last {
+                self.lost_readings += reading.timestamp - last - 1;
+            }
+        }
+        self.last_timestamp = Some(reading.timestamp);
+    }
+
+    pub fn record_duplicate(&mut self) {
+        self.duplicates += 1;
+    }
+
+    pub fn record_error(&mut self, error: &FrameError) {
+        match error {
+            FrameError::CrcMismatch { .. } => self.crc_errors += 1,
+            FrameError::Truncated => self.truncated_frames += 1,
+            FrameError::UnsupportedVersion(_) => self.version_mismatches += 1,
+            FrameError::MissingTag | FrameError::UnexpectedTag | FrameError::BadTag { .. } => {
+                self.auth_failures += 1
+            }
+            FrameError::Replayed { .. } => self.replayed_frames += 1,
+            FrameError::InvalidLength(_)
+            | FrameError::BufferOverflow
+            | FrameError::UnknownKind(_)
+            | FrameError::InvalidEndByte(_)
+            | FrameError::InsufficientData(_)
+            | FrameError::InvalidEscape(_) => self.framing_errors += 1,
+        }
+    }
+
+    /// All frames that had to be thrown away.
+    pub fn errors(&self) -> u32 {
+        self.crc_errors
+            + self.framing_errors
+            + self.truncated_frames
+            + self.version_mismatches
+            + self.auth_failures
+            + self.replayed_frames
+    }
+
+    /// Statistics for printing, with the frame rate since the previous summary.
+    ///
+    /// Starts a new interval for the frame rate.
+    pub fn summary(&mut self, now_ms: u64) -> Summary<'_> {
+        let elapsed_ms = now_ms.saturating_sub(self.interval_start_ms);
+        let frames_per_second = if elapsed_ms == 0 {
+            0.0
+        } else {
+            self.interval_frames as f32 * 1000.0 / elapsed_ms as f32
+        };
+        self.interval_start_ms = now_ms;
+        self.interval_frames = 0;
+
+        Summary {
+            statistics: self,
+            frames_per_second,
+        }
+    }
+}
+
+/// Printable snapshot of the [`Statistics`], see [`Statistics::summary`].
+pub struct Summary<'a> {
+    pub statistics: &'a Statistics,
+    pub frames_per_second: f32,
+}
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stats = self.statistics;
+
+        writeln!(f, "=== SENSOR STATISTICS ===")?;
+        writeln!(
+            f,
+            "Total frames received: {} with {} readings ({:.1} frames/s)",
+            stats.total_frames, stats.total_readings, self.frames_per_second
+        )?;
+        for (info, sensor) in SENSORS.iter().zip(&stats.sensors) {
+            if sensor.count == 0 {
+                writeln!(f, "{}: no readings", info.name)?;
+                continue;
+            }
+            let precision = info.precision as usize;
+            writeln!(
+                f,
+                "{}: {} readings, last {:.*} {unit}, min {:.*}, max {:.*}, mean {:.*}",
+                info.name,
+                sensor.count,
+                precision,
+                sensor.last,
+                precision,
+                sensor.min,
+                precision,
+                sensor.max,
+                precision,
+                sensor.mean,
+                unit = info.unit,
+            )?;
+        }
+        if stats.unknown_sensor_count > 0 {
+            writeln!(f, "Unknown sensor readings: {}", stats.unknown_sensor_count)?;
+        }
+        writeln!(
+            f,
+            "Lost readings: {}, retransmitted duplicates: {}",
+            stats.lost_readings, stats.duplicates
+        )?;
+        writeln!(
+            f,
+            "CRC failures: {}, framing errors: {}, truncated frames: {}, version mismatches: {}",
+            stats.crc_errors,
+            stats.framing_errors,
+            stats.truncated_frames,
+            stats.version_mismatches
+        )?;
+        writeln!(
+            f,
+            "Authentication failures: {}, replayed frames: {}",
+            stats.auth_failures, stats.replayed_frames
+        )?;
+        write!(f, "========================")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SENSOR_HUMIDITY, SENSOR_TEMPERATURE};
+
+    fn reading(sensor_id: u8, value: f32, timestamp: u32) -> SensorReading {
+        SensorReading {
+            sensor_id,
+            value,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn per_sensor_min_max_mean() {
+        let mut stats = Statistics::new(0);
+        stats.record_frame(&[
+            reading(SENSOR_HUMIDITY, 40.0, 0),
+            reading(SENSOR_HUMIDITY, 50.0, 1),
+        ]);
+        stats.record_frame(&[reading(SENSOR_HUMIDITY, 45.0, 2)]);
+        stats.record_frame(&[reading(SENSOR_HUMIDITY, 41.0, 3), reading(0xEE, 1.0, 4)]);
+
+        let humidity = stats.sensors[sensor_index(SENSOR_HUMIDITY).unwrap()];
+        assert_eq!(humidity.count, 4);
+        assert_eq!(humidity.last, 41.0);
+        assert_eq!(humidity.min, 40.0);
+        assert_eq!(humidity.max, 50.0);
+        assert!((humidity.mean - 44.0).abs() < 1e-4);
+
+        let temperature = stats.sensors[sensor_index(SENSOR_TEMPERATURE).unwrap()];
+        assert_eq!(temperature.count, 0);
+        assert_eq!(stats.unknown_sensor_count, 1);
+        assert_eq!(stats.total_frames, 3);
+        assert_eq!(stats.total_readings, 5);
+    }
+
+    #[test]
+    fn timestamp_gaps_count_as_lost() {
+        let mut stats = Statistics::new(0);
+        for timestamp in [1, 2, 5, 6, 9] {
+            stats.record_frame(&[reading(SENSOR_HUMIDITY, 45.0, timestamp)]);
+        }
+        assert_eq!(stats.lost_readings, 4);
+
+        // Sender restarted
+        stats.record_frame(&[
+            reading(SENSOR_HUMIDITY, 45.0, 1),
+            reading(SENSOR_HUMIDITY, 45.0, 2),
+        ]);
+        assert_eq!(stats.lost_readings, 4);
+    }
+
+    #[test]
+    fn errors_are_classified() {
+        let mut stats = Statistics::new(0);
+        stats.record_error(&FrameError::CrcMismatch {
+            seq: 1,
+            received: 0,
+            expected: 1,
+        });
+        stats.record_error(&FrameError::InvalidEndByte(0x00));
+        stats.record_error(&FrameError::InvalidLength(99));
+        stats.record_error(&FrameError::Truncated);
+        stats.record_error(&FrameError::UnsupportedVersion(0x02));
+        stats.record_error(&FrameError::BadTag { seq: 3 });
+        stats.record_error(&FrameError::MissingTag);
+        stats.record_error(&FrameError::Replayed { seq: 4, counter: 9 });
+
+        assert_eq!(stats.crc_errors, 1);
+        assert_eq!(stats.framing_errors, 2);
+        assert_eq!(stats.truncated_frames, 1);
+        assert_eq!(stats.version_mismatches, 1);
+        assert_eq!(stats.auth_failures, 2);
+        assert_eq!(stats.replayed_frames, 1);
+        assert_eq!(stats.errors(), 8);
+    }
+
+    #[test]
+    fn frame_rate_per_summary_interval() {
+        let mut stats = Statistics::new(1000);
+        for timestamp in 0..10 {
+            stats.record_frame(&[reading(SENSOR_HUMIDITY, 45.0, timestamp)]);
+        }
+        assert_eq!(stats.summary(3000).frames_per_second, 5.0);
+        assert_eq!(stats.summary(4000).frames_per_second, 0.0);
+        assert_eq!(stats.summary(4000).frames_per_second, 0.0);
+    }
+}
@@ -0,0 +1,223 @@
+//! Splits the raw byte stream into frames, broken frames and garbage, keeping the bytes each of
+//! them was made of.
+//!
+//! The decoding itself is left to the [`FrameParser`] board2 uses, so the sniffer accepts and
+//! rejects exactly what the board does.
+
+use protocol::{Frame, FrameError, FrameParser, Key, ParseState, START_BYTE};
+
+/// Something seen on the wire
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    /// Offset of the first byte in the stream
+    pub at: usize,
+    pub raw: Vec<u8>,
+    pub kind: EventKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EventKind {
+    /// A valid frame, with the address from its header
+    Frame { address: u8, frame: Frame },
+    /// A frame the board would throw away
+    Error(FrameError),
+    /// Bytes outside of any frame, including what is left of a frame after an error
+    Garbage,
+}
+
+#[derive(Default)]
+pub struct Decoder {
+    parser: FrameParser,
+    /// Bytes seen so far
+    offset: usize,
+    /// Bytes of the frame in progress, starting with its start byte
+    raw: Vec<u8>,
+    garbage: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks tags and counters like a board built with the `auth` feature.
+    pub fn authenticated(key: Key) -> Self {
+        Self {
+            parser: FrameParser::authenticated(key),
+            ..Self::default()
+        }
+    }
+
+    /// Feed the next byte, returns everything it completed.
+    pub fn push(&mut self, byte: u8) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        if self.parser.state() == &ParseState::WaitingForStart {
+            if byte != START_BYTE {
+                self.garbage.push(byte);
+                self.offset += 1;
+                return events;
+            }
+            events.extend(self.take_garbage());
+        }
+
+        let result = self.parser.process_byte(byte);
+        if let Err(error) = result {
+            if byte == START_BYTE {
+                // The parser already started over with this start byte
+                events.extend(self.take_frame(EventKind::Error(error)));
+                self.raw.push(byte);
+                self.offset += 1;
+                return events;
+            }
+        }
+
+        self.raw.push(byte);
+        self.offset += 1;
+        match result {
+            Ok(None) => {}
+            Ok(Some(frame)) => {
+                let address = self.parser.address();
+                events.extend(self.take_frame(EventKind::Frame { address, frame }));
+            }
+            Err(error) => events.extend(self.take_frame(EventKind::Error(error))),
+        }
+        events
+    }
+
+    /// The line went quiet or the capture ended, give up on the frame in progress.
+    pub fn flush(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        if let Some(error) = self.parser.on_timeout() {
+            events.extend(self.take_frame(EventKind::Error(error)));
+        }
+        events.extend(self.take_garbage());
+        events
+    }
+
+    fn take_frame(&mut self, kind: EventKind) -> Option<Event> {
+        let raw = self.raw.split_off(0);
+        Some(Event {
+            at: self.offset - raw.len(),
+            raw,
+            kind,
+        })
+    }
+
+    fn take_garbage(&mut self) -> Option<Event> {
+        if self.garbage.is_empty() {
+            return None;
+        }
+        let raw = self.garbage.split_off(0);
+        Some(Event {
+            at: self.offset - raw.len(),
+            raw,
+            kind: EventKind::Garbage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::{DataFrame, Readings, SensorReading, DIRECT_ADDRESS, SENSOR_HUMIDITY};
+
+    fn data_frame(seq: u8) -> (Frame, Vec<u8>) {
+        let frame = Frame::Data {
+            seq,
+            readings: Readings::from_slice(&[SensorReading {
+                sensor_id: SENSOR_HUMIDITY,
+                value: 45.0,
+                timestamp: seq as u32,
+            }])
+            .unwrap(),
+        };
+        let mut encoder = DataFrame::new();
+        encoder.build_frame(&frame).unwrap();
+        (frame, encoder.get_bytes().to_vec())
+    }
+
+    fn decode(bytes: &[u8]) -> Vec<Event> {
+        let mut decoder = Decoder::new();
+        let mut events: Vec<Event> = bytes.iter().flat_map(|&byte| decoder.push(byte)).collect();
+        events.extend(decoder.flush());
+        events
+    }
+
+    fn valid(frame: Frame) -> EventKind {
+        EventKind::Frame {
+            address: DIRECT_ADDRESS,
+            frame,
+        }
+    }
+
+    fn event(at: usize, raw: &[u8], kind: EventKind) -> Event {
+        Event {
+            at,
+            raw: raw.to_vec(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn frames_between_garbage() {
+        let (first, first_bytes) = data_frame(1);
+        let (second, second_bytes) = data_frame(2);
+        let mut bytes = vec![0x00, 0x13];
+        bytes.extend(&first_bytes);
+        bytes.push(0xFF);
+        bytes.extend(&second_bytes);
+        bytes.push(0x42);
+
+        let second_at = 2 + first_bytes.len() + 1;
+        assert_eq!(
+            decode(&bytes),
+            [
+                event(0, &[0x00, 0x13], EventKind::Garbage),
+                event(2, &first_bytes, valid(first)),
+                event(2 + first_bytes.len(), &[0xFF], EventKind::Garbage),
+                event(second_at, &second_bytes, valid(second)),
+                event(second_at + second_bytes.len(), &[0x42], EventKind::Garbage),
+            ]
+        );
+    }
+
+    #[test]
+    fn broken_frames_keep_their_bytes() {
+        let (_, mut corrupted) = data_frame(1);
+        // Flip a bit of the reading value
+        corrupted[8] ^= 0x01;
+        let (_, cut_off) = data_frame(2);
+        let cut_off = &cut_off[..6];
+        let (frame, complete) = data_frame(3);
+
+        let mut bytes = corrupted.clone();
+        bytes.extend(cut_off);
+        bytes.extend(&complete);
+        bytes.extend(&complete[..3]);
+
+        let events = decode(&bytes);
+        assert_eq!(events.len(), 5);
+        // The CRC is wrong before the end byte arrives, which is left over
+        let end = corrupted.len() - 1;
+        assert!(matches!(
+            events[0].kind,
+            EventKind::Error(FrameError::CrcMismatch { seq: 1, .. })
+        ));
+        assert_eq!(events[0].raw, corrupted[..end]);
+        assert_eq!(events[1], event(end, &corrupted[end..], EventKind::Garbage));
+
+        let at = corrupted.len();
+        assert_eq!(
+            events[2],
+            event(at, cut_off, EventKind::Error(FrameError::Truncated))
+        );
+        let at = at + cut_off.len();
+        assert_eq!(events[3], event(at, &complete, valid(frame)));
+        let at = at + complete.len();
+        assert_eq!(
+            events[4],
+            event(at, &complete[..3], EventKind::Error(FrameError::Truncated))
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Shows what is on the wire between board1 and board2.
+//!
+//! ```text
+//! sniffer /dev/ttyUSB0 [baud]    # live, from a USB-UART adapter on one of the lines
+//! sniffer --file capture.bin     # from a raw capture
+//! sniffer --key <32 hex digits> ...   # an authenticated link, with the key of the boards
+//! sniffer --gap-ms 100 ...       # silence after which a started frame counts as cut short
+//! ```
+//!
+//! Valid frames are printed in green, frames the board would reject in red and bytes outside of
+//! any frame in yellow, each with its raw bytes and
+//! the offset where it started. A summary follows at the end of a capture.
+//!
+//! The boards start at 9600 baud and then agree on a faster rate, see the BAUD frames. A live
+//! sniffer only follows at the rate it was started with, so give it the rate they settled on.
+//!
+//! USB-UART adapters pass on what they received in chunks, FTDI's every 16 ms by default, so
+//! a live frame only counts as cut short after a longer silence than the boards allow, see
+//! [`gap_timeout`]. Raise it with `--gap-ms` if good frames show up as truncated.
+
+mod decoder;
+
+use std::{
+    env,
+    error::Error,
+    fs::File,
+    io::{self, Read},
+    process,
+    time::Duration,
+};
+
+use decoder::{Decoder, Event, EventKind};
+use protocol::{
+    baud::transfer_time_ms, sensor_info, Frame, Key, SensorReading, Statistics, DIRECT_ADDRESS,
+};
+
+const DEFAULT_BAUD_RATE: u32 = 115_200;
+
+/// How late a USB-UART adapter may pass on bytes, FTDI's latency timer is 16 ms by default
+const ADAPTER_LATENCY_MS: u64 = 20;
+
+/// Byte times of silence within a frame on top of the adapter's latency
+const GAP_BYTES: usize = 4;
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut args = args.as_slice();
+    let (mut key, mut gap) = (None, None);
+    loop {
+        match args {
+            [flag, value, rest @ ..] if flag == "--key" => {
+                key = Some(parse_key(value)?);
+                args = rest;
+            }
+            [flag, value, rest @ ..] if flag == "--gap-ms" => {
+                gap = Some(Duration::from_millis(value.parse()?));
+                args = rest;
+            }
+            _ => break,
+        }
+    }
+    match args {
+        [flag, path] if flag == "--file" => sniff_file(path, key),
+        [port] => sniff_port(port, DEFAULT_BAUD_RATE, key, gap),
+        [port, baud] => sniff_port(port, baud.parse()?, key, gap),
+        _ => {
+            eprintln!(
+                "Usage: sniffer [--key <key>] [--gap-ms <ms>] <serial port> [baud] | sniffer [--key <key>] --file <capture>"
+            );
+            process::exit(2);
+        }
+    }
+}
+
+/// Silence after which a frame in progress on a live port counts as cut short: the adapter's
+/// latency and a few byte times, which matter at the slow rates.
+fn gap_timeout(baud_rate: u32) -> Duration {
+    Duration::from_millis(ADAPTER_LATENCY_MS + transfer_time_ms(GAP_BYTES, baud_rate))
+}
+
+fn parse_key(hex: &str) -> Result<Key, Box<dyn Error>> {
+    if hex.len() != 32 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err("the key must be 32 hex digits, like LINK_KEY of the boards".into());
+    }
+    Ok(Key::from_hex(hex))
+}
+
+fn sniff_file(path: &str, key: Option<Key>) -> Result<(), Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+
+    let mut sniffer = Sniffer::new(key);
+    for &byte in &bytes {
+        sniffer.push(byte);
+    }
+    sniffer.flush();
+
+    println!("\n{}", sniffer.stats.summary(0));
+    Ok(())
+}
+
+fn sniff_port(
+    name: &str,
+    baud_rate: u32,
+    key: Option<Key>,
+    gap: Option<Duration>,
+) -> Result<(), Box<dyn Error>> {
+    let gap = gap.unwrap_or_else(|| gap_timeout(baud_rate));
+    // Only a silence that long ends a frame in progress
+    let mut port = serialport::new(name, baud_rate).timeout(gap).open()?;
+    println!(
+        "Sniffing {name} at {baud_rate} baud, frames end after {} ms of silence",
+        gap.as_millis()
+    );
+
+    let mut sniffer = Sniffer::new(key);
+    let mut buffer = [0u8; 256];
+    loop {
+        match port.read(&mut buffer) {
+            Ok(count) => {
+                for &byte in &buffer[..count] {
+                    sniffer.push(byte);
+                }
+            }
+            Err(error) if error.kind() == io::ErrorKind::TimedOut => sniffer.flush(),
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+struct Sniffer {
+    decoder: Decoder,
+    stats: Statistics,
+}
+
+impl Sniffer {
+    fn new(key: Option<Key>) -> Self {
+        Self {
+            decoder: key.map_or_else(Decoder::new, Decoder::authenticated),
+            // The frame rate in the summary is of no interest here
+            stats: Statistics::new(0),
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        for event in self.decoder.push(byte) {
+            self.show(&event);
+        }
+    }
+
+    fn flush(&mut self) {
+        for event in self.decoder.flush() {
+            self.show(&event);
+        }
+    }
+
+    fn show(&mut self, event: &Event) {
+        let Event { at, raw, kind } = event;
+        match kind {
+            EventKind::Frame { address, frame } => self.show_frame(*at, *address, frame, raw),
+            EventKind::Error(error) => {
+                self.stats.record_error(error);
+                println!("{RED}@{at:<8} {error}{RESET}  {}", hex(raw));
+            }
+            EventKind::Garbage => println!(
+                "{YELLOW}@{at:<8} {} garbage bytes{RESET}  {}",
+                raw.len(),
+                hex(raw)
+            ),
+        }
+    }
+
+    fn show_frame(&mut self, at: usize, address: u8, frame: &Frame, raw: &[u8]) {
+        // Only bus frames carry a meaningful address
+        let to = if address == DIRECT_ADDRESS {
+            String::new()
+        } else {
+            format!(" node {address}")
+        };
+        match frame {
+            Frame::Data { seq, readings } => {
+                self.stats.record_frame(readings);
+                println!(
+                    "{GREEN}@{at:<8} DATA{to} seq {seq}, {} readings{RESET}  {}",
+                    readings.len(),
+                    hex(raw)
+                );
+                for reading in readings {
+                    println!("    {}", describe(reading));
+                }
+            }
+            Frame::Ack { seq, max_readings } => println!(
+                "{GREEN}@{at:<8} ACK{to} seq {seq}, up to {max_readings} readings{RESET}  {}",
+                hex(raw)
+            ),
+            Frame::Nack { seq } => {
+                println!("{GREEN}@{at:<8} NACK{to} seq {seq}{RESET}  {}", hex(raw))
+            }
+            Frame::Poll { max_readings } => println!(
+                "{GREEN}@{at:<8} POLL{to}, up to {max_readings} readings{RESET}  {}",
+                hex(raw)
+            ),
+            Frame::Idle => println!("{GREEN}@{at:<8} IDLE{to}{RESET}  {}", hex(raw)),
+            Frame::Baud { rate } => println!("{GREEN}@{at:<8} BAUD {rate}{RESET}  {}", hex(raw)),
+            Frame::Probe { seq } => println!("{GREEN}@{at:<8} PROBE {seq}{RESET}  {}", hex(raw)),
+        }
+    }
+}
+
+fn describe(reading: &SensorReading) -> String {
+    match sensor_info(reading.sensor_id) {
+        Some(info) => format!(
+            "{} {}: {:.*} {} [timestamp: {}]{}",
+            info.icon,
+            info.name,
+            info.precision as usize,
+            reading.value,
+            info.unit,
+            reading.timestamp,
+            if info.in_range(reading.value) {
+                ""
+            } else {
+                " out of range"
+            }
+        ),
+        None => format!(
+            "Unknown sensor {:#04X}: {} [timestamp: {}]",
+            reading.sensor_id, reading.value, reading.timestamp
+        ),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
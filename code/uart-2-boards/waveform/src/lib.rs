@@ -0,0 +1,282 @@
+//! Signal models for fake sensors.
+//!
+//! A [`Model`] describes what a sensor should look like: a [`Shape`] around a base value, some
+//! uniform noise on top, and optionally a [`Fault`] to see how the receiving side copes with
+//! bad data. A [`Signal`] turns it into one value per sample.
+//!
+//! Models are plain `const` data, so a board can pick them per sensor at build time. Everything
+//! is deterministic for a given random number generator, which makes the signals reproducible
+//! and testable on the host with `cargo test`.
+
+#![no_std]
+
+use core::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use rand_core::RngCore;
+
+/// The signal without noise and faults
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+    /// Always the base value
+    Constant,
+    /// Oscillates around the base value, `period` is in samples
+    Sine { amplitude: f32, period: u32 },
+    /// Moves by up to `step` per sample, never further than `limit` away from the base value
+    RandomWalk { step: f32, limit: f32 },
+    /// Jumps between the base value and base + `height` every `every` samples
+    Steps { height: f32, every: u32 },
+}
+
+/// Something wrong with the sensor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// Every `every`th sample is off by `height`
+    Spike { height: f32, every: u32 },
+    /// From sample `after` on the value doesn't change anymore
+    StuckAt { after: u32 },
+    /// Samples go missing with the given probability in percent
+    Dropout { percent: u8 },
+}
+
+/// Everything needed to generate a sensor signal
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Model {
+    pub base: f32,
+    pub shape: Shape,
+    /// Uniform noise of up to ± this much on every sample
+    pub noise: f32,
+    pub fault: Option<Fault>,
+}
+
+impl Model {
+    /// Noise around a fixed value, with no fault.
+    pub const fn noisy(base: f32, noise: f32) -> Self {
+        Self {
+            base,
+            shape: Shape::Constant,
+            noise,
+            fault: None,
+        }
+    }
+
+    pub const fn with_shape(self, shape: Shape) -> Self {
+        Self { shape, ..self }
+    }
+
+    pub const fn with_fault(self, fault: Fault) -> Self {
+        Self {
+            fault: Some(fault),
+            ..self
+        }
+    }
+}
+
+/// Generates the samples of a [`Model`]
+#[derive(Clone, Debug)]
+pub struct Signal {
+    model: Model,
+    sample: u32,
+    /// Current offset of the random walk from the base value
+    walk: f32,
+    stuck: Option<f32>,
+}
+
+impl Signal {
+    pub const fn new(model: Model) -> Self {
+        Self {
+            model,
+            sample: 0,
+            walk: 0.0,
+            stuck: None,
+        }
+    }
+
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    /// The next sample, `None` when it dropped out.
+    pub fn next(&mut self, rng: &mut impl RngCore) -> Option<f32> {
+        let sample = self.sample;
+        self.sample = self.sample.wrapping_add(1);
+
+        let mut value = self.model.base + self.shape(sample, rng);
+        value += self.model.noise * uniform(rng);
+
+        match self.model.fault {
+            None => Some(value),
+            Some(Fault::Spike { height, every }) => {
+                let spike = every > 0 && sample % every == every - 1;
+                Some(if spike { value + height } else { value })
+            }
+            Some(Fault::StuckAt { after }) => {
+                if sample >= after {
+                    Some(*self.stuck.get_or_insert(value))
+                } else {
+                    Some(value)
+                }
+            }
+            Some(Fault::Dropout { percent }) => {
+                let dropped = rng.next_u32() % 100 < percent as u32;
+                (!dropped).then_some(value)
+            }
+        }
+    }
+
+    fn shape(&mut self, sample: u32, rng: &mut impl RngCore) -> f32 {
+        match self.model.shape {
+            Shape::Constant => 0.0,
+            Shape::Sine { amplitude, period } => {
+                // Reduce in integers first, a large sample count would eat the precision
+                let phase = (sample % period.max(1)) as f32 / period.max(1) as f32;
+                amplitude * sin(TAU * phase)
+            }
+            Shape::RandomWalk { step, limit } => {
+                self.walk = (self.walk + step * uniform(rng)).clamp(-limit, limit);
+                self.walk
+            }
+            Shape::Steps { height, every } => {
+                if (sample / every.max(1)) % 2 == 1 {
+                    height
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// Uniformly distributed in [-1, 1).
+fn uniform(rng: &mut impl RngCore) -> f32 {
+    // 24 bits is all the precision an f32 has
+    (rng.next_u32() >> 8) as f32 / (1u32 << 23) as f32 - 1.0
+}
+
+/// Sine for `no_std`, good to about 1e-5, which is plenty for a fake sensor.
+fn sin(x: f32) -> f32 {
+    // Into [-π, π], then mirror into [-π/2, π/2] where the Taylor series converges quickly
+    let turns = x / TAU;
+    let mut x = (turns - turns as i32 as f32) * TAU;
+    if x > PI {
+        x -= TAU;
+    } else if x < -PI {
+        x += TAU;
+    }
+    if x > FRAC_PI_2 {
+        x = PI - x;
+    } else if x < -FRAC_PI_2 {
+        x = -PI - x;
+    }
+
+    let x2 = x * x;
+    x * (1.0 - x2 / 6.0 * (1.0 - x2 / 20.0 * (1.0 - x2 / 42.0 * (1.0 - x2 / 72.0))))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+    use std::vec::Vec;
+
+    fn samples(model: Model, count: usize) -> Vec<Option<f32>> {
+        let mut rng = SmallRng::seed_from_u64(12345);
+        let mut signal = Signal::new(model);
+        (0..count).map(|_| signal.next(&mut rng)).collect()
+    }
+
+    fn values(model: Model, count: usize) -> Vec<f32> {
+        samples(model, count)
+            .into_iter()
+            .map(|value| value.expect("no dropouts expected"))
+            .collect()
+    }
+
+    #[test]
+    fn sine_approximation() {
+        for step in -1000..=1000 {
+            let x = step as f32 / 100.0;
+            assert!((sin(x) - x.sin()).abs() < 1e-5, "sin({x})");
+        }
+    }
+
+    #[test]
+    fn noise_stays_within_bounds() {
+        let values = values(Model::noisy(45.0, 10.0), 1000);
+        assert!(values.iter().all(|value| (35.0..55.0).contains(value)));
+        // Not just the same value over and over
+        assert!(values.iter().any(|value| *value < 40.0));
+        assert!(values.iter().any(|value| *value > 50.0));
+    }
+
+    #[test]
+    fn sine_follows_the_period() {
+        let model = Model::noisy(20.0, 0.0).with_shape(Shape::Sine {
+            amplitude: 5.0,
+            period: 8,
+        });
+        let values = values(model, 16);
+        for (sample, expected) in [(0, 20.0), (2, 25.0), (4, 20.0), (6, 15.0), (10, 25.0)] {
+            assert!((values[sample] - expected).abs() < 1e-4, "sample {sample}");
+        }
+    }
+
+    #[test]
+    fn random_walk_stays_within_limit() {
+        let model = Model::noisy(1013.0, 0.0).with_shape(Shape::RandomWalk {
+            step: 2.0,
+            limit: 10.0,
+        });
+        let values = values(model, 2000);
+        assert!(values
+            .windows(2)
+            .all(|pair| (pair[1] - pair[0]).abs() <= 2.0));
+        assert!(values.iter().all(|value| (1003.0..=1023.0).contains(value)));
+    }
+
+    #[test]
+    fn steps_alternate() {
+        let model = Model::noisy(300.0, 0.0).with_shape(Shape::Steps {
+            height: 200.0,
+            every: 3,
+        });
+        assert_eq!(
+            values(model, 8),
+            [300.0, 300.0, 300.0, 500.0, 500.0, 500.0, 300.0, 300.0]
+        );
+    }
+
+    #[test]
+    fn spikes_come_regularly() {
+        let model = Model::noisy(3.3, 0.0).with_fault(Fault::Spike {
+            height: 10.0,
+            every: 4,
+        });
+        let values = values(model, 8);
+        assert_eq!(values[3], 13.3);
+        assert_eq!(values[7], 13.3);
+        assert_eq!(values.iter().filter(|value| **value == 3.3).count(), 6);
+    }
+
+    #[test]
+    fn stuck_sensor_repeats_itself() {
+        let model = Model::noisy(22.0, 3.0).with_fault(Fault::StuckAt { after: 5 });
+        let values = values(model, 20);
+        assert_ne!(values[3], values[4]);
+        assert!(values[5..].iter().all(|value| *value == values[5]));
+    }
+
+    #[test]
+    fn dropouts_match_the_probability() {
+        let model = Model::noisy(45.0, 1.0).with_fault(Fault::Dropout { percent: 25 });
+        let dropped = samples(model, 4000)
+            .iter()
+            .filter(|value| value.is_none())
+            .count();
+        assert!((900..1100).contains(&dropped), "{dropped} dropped");
+
+        let never = Model::noisy(45.0, 1.0).with_fault(Fault::Dropout { percent: 0 });
+        assert!(samples(never, 100).iter().all(Option::is_some));
+    }
+}